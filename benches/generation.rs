@@ -0,0 +1,36 @@
+//! Generation latency by group size on the in-memory transport.
+//!
+//! Run with `cargo bench --bench generation --features test-util,tokio`. Every timed run
+//! covers the invitations, all three DKG rounds and the participants' reports back to the
+//! requester.
+
+use std::time::Instant;
+
+use frostore::TestCluster;
+
+const GROUP_SIZES: [u16; 3] = [2, 4, 10];
+const RUNS: usize = 10;
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    for total_peers in GROUP_SIZES {
+        let mut cluster = TestCluster::new(total_peers as usize).await.unwrap();
+        let min_threshold = total_peers * 2 / 3;
+        let mut samples = Vec::with_capacity(RUNS);
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            cluster
+                .generate(min_threshold.max(2), total_peers)
+                .await
+                .unwrap();
+            samples.push(start.elapsed());
+        }
+        samples.sort();
+        println!(
+            "{total_peers:>2} signers: median {:>8.2?} min {:>8.2?} max {:>8.2?}",
+            samples[RUNS / 2],
+            samples[0],
+            samples[RUNS - 1],
+        );
+    }
+}
@@ -56,5 +56,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let valid = pubkey.verify(&data, &signature).is_ok();
     println!("Signature Valid: {}", valid);
     assert!(valid);
+
+    // Generate a second, independent group and sign with it
+    let other_pubkey = swarm.generate(MIN_THRESHOLD, TOTAL_PEERS).1.await?;
+    println!("Generated second pubkey: {:?}", other_pubkey);
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let other_signature = swarm.sign(other_pubkey, data.clone()).1.await?;
+    assert!(other_pubkey.verify(&data, &other_signature).is_ok());
+    assert!(pubkey.verify(&data, &other_signature).is_err());
+    println!("Second group signature valid: true");
     Ok(())
 }
@@ -34,11 +34,23 @@ async fn main() -> Result<()> {
                         let _ = args.next();
 
                         // get the multiaddr from the input
-                        let multiaddr: Multiaddr = args.next().unwrap().parse()?;
+                        let multiaddr = match args.next().map(str::parse::<Multiaddr>) {
+                            Some(Ok(multiaddr)) => multiaddr,
+                            Some(Err(error)) => {
+                                eprintln!("Invalid multiaddr: {}", error);
+                                continue;
+                            },
+                            None => {
+                                eprintln!("Usage: ADD_PEER <multiaddr>");
+                                continue;
+                            },
+                        };
 
                         // add the peer to the swarm network
-                        swarm.add_peer(multiaddr.clone())?;
-                        eprintln!("Added peer: {}", multiaddr);
+                        match swarm.add_peer(multiaddr.clone()) {
+                            Ok(()) => eprintln!("Added peer: {}", multiaddr),
+                            Err(error) => eprintln!("Error: {}", error),
+                        }
                     },
                     // Begin generation of a new keypair using DKG (Distributed Key Generation) on the swarm network
                     line if line.starts_with("GENERATE") => {
@@ -73,14 +85,51 @@ async fn main() -> Result<()> {
                         println!("Generated Key: {}", b64.encode(pubkey.serialize()));
                         println!("Onion Address: {}", onion_address(pubkey.serialize().to_vec()));
                     },
+                    // Progress through the rounds of a key generation
+                    SwarmOutput::GenerationProgress(_, round, received, expected) => {
+                        eprintln!("Generation {:?}: {}/{}", round, received, expected);
+                    },
+                    // Joined a group generated by another node
+                    SwarmOutput::JoinedGroup(pubkey, _, participants) => {
+                        eprintln!("Joined {:?} with {} participants", pubkey, participants.len());
+                    },
+                    // Peers joining and leaving the routing table
+                    SwarmOutput::PeerDiscovered(peer_id, _) => {
+                        eprintln!("Discovered {}", peer_id);
+                    },
+                    SwarmOutput::PeerExpired(peer_id) => {
+                        eprintln!("Expired {}", peer_id);
+                    },
+                    SwarmOutput::ListenFailed(address, reason) => {
+                        eprintln!("Failed to listen on {}: {}", address, reason);
+                    },
+                    SwarmOutput::BootstrapComplete => {
+                        eprintln!("Bootstrap complete");
+                    },
+                    SwarmOutput::TopologySnapshot(topology) => {
+                        eprintln!("Connected to {} peers", topology.connected_peers.len());
+                    },
+                    SwarmOutput::NatStatus(status) => {
+                        eprintln!("NAT status: {status:?}");
+                    },
                     // Finished signing a message
-                    SwarmOutput::Signing(id, signature) => {
+                    SwarmOutput::Signing(id, signature, participants) => {
                         let (pubkey, message) = request_db.get(&id).unwrap();
                         println!("Message: {}", String::from_utf8_lossy(message));
                         println!("Signature: {:?}", signature);
+                        println!("Signers: {:?}", participants.values().collect::<Vec<_>>());
                         let valid = pubkey.verify(message, &signature).is_ok();
                         println!("Signature Valid: {}", valid);
                     },
+                    SwarmOutput::PartialSignatureReceived(id, identifier) => {
+                        eprintln!("Signing {}: share from {:?}", id, identifier);
+                    },
+                    SwarmOutput::SigningFailed(id, reason) => {
+                        eprintln!("Signing {} failed: {}", id, reason);
+                    },
+                    SwarmOutput::Message { topic, .. } => {
+                        eprintln!("Message on {}", topic);
+                    },
                     // Swarm network events
                     SwarmOutput::SwarmEvents(event) => {
                         match event {
@@ -0,0 +1,171 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use dashmap::DashMap;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{
+    core::{
+        muxing::{StreamMuxerBox, StreamMuxerEvent, StreamMuxerExt, SubstreamBox},
+        transport::Boxed,
+        StreamMuxer,
+    },
+    PeerId, Transport,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerBandwidth {
+    pub inbound: u64,
+    pub outbound: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl Counters {
+    fn load(&self) -> PeerBandwidth {
+        PeerBandwidth {
+            inbound: self.inbound.load(Ordering::Relaxed),
+            outbound: self.outbound.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Bandwidth {
+    total: Arc<Counters>,
+    peers: DashMap<PeerId, Arc<Counters>>,
+}
+
+impl Bandwidth {
+    pub(crate) fn wrap(
+        self: &Arc<Self>,
+        transport: Boxed<(PeerId, StreamMuxerBox)>,
+    ) -> Boxed<(PeerId, StreamMuxerBox)> {
+        let bandwidth = self.clone();
+        transport
+            .map(move |(peer, muxer), _| {
+                let peer_counters = bandwidth.peers.entry(peer).or_default().clone();
+                let muxer = Muxer {
+                    inner: muxer,
+                    peer: peer_counters,
+                    total: bandwidth.total.clone(),
+                };
+                (peer, StreamMuxerBox::new(muxer))
+            })
+            .boxed()
+    }
+
+    pub(crate) fn total(&self) -> PeerBandwidth {
+        self.total.load()
+    }
+
+    pub(crate) fn peers(&self) -> Vec<(PeerId, PeerBandwidth)> {
+        self.peers
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().load()))
+            .collect()
+    }
+}
+
+struct Muxer {
+    inner: StreamMuxerBox,
+    peer: Arc<Counters>,
+    total: Arc<Counters>,
+}
+
+impl Muxer {
+    fn substream(&self, inner: SubstreamBox) -> Substream {
+        Substream {
+            inner,
+            peer: self.peer.clone(),
+            total: self.total.clone(),
+        }
+    }
+}
+
+impl StreamMuxer for Muxer {
+    type Substream = Substream;
+    type Error = io::Error;
+
+    fn poll_inbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let substream = futures::ready!(self.inner.poll_inbound_unpin(cx))?;
+        Poll::Ready(Ok(self.substream(substream)))
+    }
+
+    fn poll_outbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let substream = futures::ready!(self.inner.poll_outbound_unpin(cx))?;
+        Poll::Ready(Ok(self.substream(substream)))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        self.inner.poll_unpin(cx)
+    }
+}
+
+struct Substream {
+    inner: SubstreamBox,
+    peer: Arc<Counters>,
+    total: Arc<Counters>,
+}
+
+impl Substream {
+    fn record(&self, bytes: usize, select: fn(&Counters) -> &AtomicU64) {
+        select(&self.peer).fetch_add(bytes as u64, Ordering::Relaxed);
+        select(&self.total).fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for Substream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let read = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.record(read, |counters| &counters.inbound);
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncWrite for Substream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.record(written, |counters| &counters.outbound);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -1,23 +1,57 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc, time::Duration};
+
 use futures::future::BoxFuture;
+use libp2p::gossipsub;
 
-use crate::{swarm::Swarm, Keypair, Multiaddr};
+use crate::{
+    swarm::{
+        Authenticity, ConnectionLimitConfig, EventFilter, MessageValidator, PeerScoring, RateLimit,
+        RequestResponseConfig, Security, SigningMode, Swarm, TimeoutConfig, TopicHashing,
+        TransportKind,
+    },
+    Keypair, Multiaddr, PeerId, StorageBackend,
+};
 
 pub struct Builder {
     pub key: Keypair,
     pub addresses: Vec<Multiaddr>,
     pub(crate) executor: fn(BoxFuture<'static, ()>),
+    pub storage: StorageBackend,
+    pub timeouts: TimeoutConfig,
+    pub transport: TransportKind,
+    pub authenticity: Authenticity,
+    pub security: Security,
+    pub signing_mode: SigningMode,
+    pub topic_hashing: TopicHashing,
+    pub event_filter: EventFilter,
+    pub network_id: Option<String>,
+    pub max_concurrent_sessions: Option<usize>,
+    pub rate_limit: RateLimit,
+    pub peer_scoring: Option<PeerScoring>,
+    pub connection_limits: ConnectionLimitConfig,
+    pub request_response: RequestResponseConfig,
+    pub gossipsub_config: Option<gossipsub::Config>,
+    pub message_validator: Option<MessageValidator>,
+    pub relay: bool,
+    pub peer_cache: Option<PathBuf>,
+    pub bootstrap_peers: Vec<Multiaddr>,
+    pub topology_interval: Option<Duration>,
+    pub input_capacity: usize,
+    pub generate_retries: u32,
+    pub allowed_peers: Option<HashSet<PeerId>>,
+    pub denied_peers: HashSet<PeerId>,
+    pub idle_connection_timeout: Duration,
+    #[cfg(feature = "test-util")]
+    pub rng_seed: Option<u64>,
 }
 
 impl Default for Builder {
     fn default() -> Self {
         Self {
             key: Keypair::generate_ed25519(),
-            addresses: vec![
-                "/ip4/0.0.0.0/tcp/0".parse().unwrap(),
-                "/ip4/0.0.0.0/udp/0/quic-v1".parse().unwrap(),
-            ],
+            addresses: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
             executor: |_fut| {
-                #[cfg(all(feature = "tokio", not(feature = "async-std")))]
+                #[cfg(feature = "tokio")]
                 tokio::spawn(_fut);
                 #[cfg(all(feature = "async-std", not(feature = "tokio")))]
                 async_std::task::spawn(_fut);
@@ -26,6 +60,33 @@ impl Default for Builder {
                     "No executor found. Please enable one of the following features: tokio, async-std; or provide your own executor."
                 );
             },
+            storage: StorageBackend::default(),
+            timeouts: TimeoutConfig::default(),
+            transport: TransportKind::default(),
+            authenticity: Authenticity::default(),
+            security: Security::default(),
+            signing_mode: SigningMode::default(),
+            topic_hashing: TopicHashing::default(),
+            event_filter: EventFilter::default(),
+            network_id: None,
+            max_concurrent_sessions: None,
+            rate_limit: RateLimit::default(),
+            peer_scoring: Some(PeerScoring::default()),
+            connection_limits: ConnectionLimitConfig::default(),
+            request_response: RequestResponseConfig::default(),
+            gossipsub_config: None,
+            message_validator: None,
+            relay: false,
+            peer_cache: None,
+            bootstrap_peers: Vec::new(),
+            topology_interval: None,
+            input_capacity: 1024,
+            generate_retries: 0,
+            allowed_peers: None,
+            denied_peers: HashSet::new(),
+            idle_connection_timeout: Duration::from_secs(60),
+            #[cfg(feature = "test-util")]
+            rng_seed: None,
         }
     }
 }
@@ -55,6 +116,166 @@ impl Builder {
         self
     }
 
+    pub fn set_storage(mut self, storage: StorageBackend) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn set_timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Defaults to TCP. `Quic` and `Both` also need a `/udp/<port>/quic-v1` listen address.
+    pub fn set_transport(mut self, transport: TransportKind) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn set_authenticity(mut self, authenticity: Authenticity) -> Self {
+        self.authenticity = authenticity;
+        self
+    }
+
+    /// Peers must agree on the security protocol; a noise node and a TLS node fail protocol
+    /// negotiation instead of connecting. QUIC connections always use TLS.
+    pub fn set_security(mut self, security: Security) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// In `Distributed` mode the first signer to aggregate also gossips the signature to the
+    /// group, so the result survives the requester dropping and others skip returning their own.
+    pub fn set_signing_mode(mut self, signing_mode: SigningMode) -> Self {
+        self.signing_mode = signing_mode;
+        self
+    }
+
+    /// Every participant of a generation must use the same strategy.
+    pub fn set_topic_hashing(mut self, topic_hashing: TopicHashing) -> Self {
+        self.topic_hashing = topic_hashing;
+        self
+    }
+
+    pub fn set_event_filter(mut self, event_filter: EventFilter) -> Self {
+        self.event_filter = event_filter;
+        self
+    }
+
+    /// Appends `/{network_id}` to the identify, request-response and Kademlia protocols, so
+    /// swarms on different networks never complete a handshake.
+    pub fn set_network_id(mut self, network_id: String) -> Self {
+        self.network_id = Some(network_id);
+        self
+    }
+
+    pub fn set_max_concurrent_sessions(mut self, max_concurrent_sessions: usize) -> Self {
+        self.max_concurrent_sessions = Some(max_concurrent_sessions);
+        self
+    }
+
+    /// `exec` fails with `ConfigurationError` unless `burst` is non-zero and `per_second` is a
+    /// positive, finite rate.
+    pub fn set_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Pass `None` to disable gossipsub peer scoring.
+    pub fn set_peer_scoring(mut self, peer_scoring: Option<PeerScoring>) -> Self {
+        self.peer_scoring = peer_scoring;
+        self
+    }
+
+    pub fn set_connection_limits(mut self, connection_limits: ConnectionLimitConfig) -> Self {
+        self.connection_limits = connection_limits;
+        self
+    }
+
+    pub fn set_request_response(mut self, request_response: RequestResponseConfig) -> Self {
+        self.request_response = request_response;
+        self
+    }
+
+    pub fn set_gossipsub_config(mut self, gossipsub_config: gossipsub::Config) -> Self {
+        self.gossipsub_config = Some(gossipsub_config);
+        self
+    }
+
+    /// Switches gossipsub to manual validation. Protocol messages that fail the swarm's own
+    /// checks are rejected as well.
+    pub fn set_message_validator(
+        mut self,
+        message_validator: impl Fn(&gossipsub::Message) -> gossipsub::MessageAcceptance
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.message_validator = Some(Arc::new(message_validator));
+        self
+    }
+
+    pub fn set_relay(mut self, relay: bool) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    pub fn set_peer_cache(mut self, peer_cache: PathBuf) -> Self {
+        self.peer_cache = Some(peer_cache);
+        self
+    }
+
+    /// Seed nodes dialed on startup; `SwarmOutput::BootstrapComplete` follows the first bootstrap.
+    pub fn set_bootstrap_peers(mut self, bootstrap_peers: Vec<Multiaddr>) -> Self {
+        self.bootstrap_peers = bootstrap_peers;
+        self
+    }
+
+    /// Emits `SwarmOutput::TopologySnapshot` roughly every `topology_interval`.
+    pub fn set_topology_interval(mut self, topology_interval: Duration) -> Self {
+        self.topology_interval = Some(topology_interval);
+        self
+    }
+
+    /// Requests issued while this many are still queued for the swarm loop fail with
+    /// `SwarmError::Busy`.
+    pub fn set_input_capacity(mut self, input_capacity: usize) -> Self {
+        self.input_capacity = input_capacity;
+        self
+    }
+
+    /// Restarts a generation that timed out or lost a participant up to `generate_retries`
+    /// times with fresh peers. Retries run under their own session id, but progress, results
+    /// and `cancel` keep using the id the caller started with.
+    pub fn set_generate_retries(mut self, generate_retries: u32) -> Self {
+        self.generate_retries = generate_retries;
+        self
+    }
+
+    /// Only these peers may connect, gossip or send requests. Unset admits everyone.
+    pub fn set_allowed_peers(mut self, allowed_peers: HashSet<PeerId>) -> Self {
+        self.allowed_peers = Some(allowed_peers);
+        self
+    }
+
+    pub fn set_denied_peers(mut self, denied_peers: HashSet<PeerId>) -> Self {
+        self.denied_peers = denied_peers;
+        self
+    }
+
+    /// Peers in the gossipsub mesh of a group topic are kept alive regardless of this timeout.
+    pub fn set_idle_connection_timeout(mut self, idle_connection_timeout: Duration) -> Self {
+        self.idle_connection_timeout = idle_connection_timeout;
+        self
+    }
+
+    /// Seeds round 1 nonces so tests can assert exact commitments. Never use outside of tests.
+    #[cfg(feature = "test-util")]
+    pub fn set_rng_seed(mut self, rng_seed: u64) -> Self {
+        self.rng_seed = Some(rng_seed);
+        self
+    }
+
     pub fn build(self) -> Swarm {
         Swarm {
             input_tx: None,
@@ -62,6 +283,35 @@ impl Builder {
             key: self.key,
             addresses: self.addresses,
             executor: self.executor,
+            storage: self.storage,
+            timeouts: self.timeouts,
+            transport: self.transport,
+            authenticity: self.authenticity,
+            security: self.security,
+            signing_mode: self.signing_mode,
+            topic_hashing: self.topic_hashing,
+            event_filter: self.event_filter,
+            network_id: self.network_id,
+            max_concurrent_sessions: self.max_concurrent_sessions,
+            rate_limit: self.rate_limit,
+            peer_scoring: self.peer_scoring,
+            connection_limits: self.connection_limits,
+            request_response: self.request_response,
+            gossipsub_config: self.gossipsub_config,
+            message_validator: self.message_validator,
+            relay: self.relay,
+            peer_cache: self.peer_cache,
+            bootstrap_peers: self.bootstrap_peers,
+            topology_interval: self.topology_interval,
+            input_capacity: self.input_capacity,
+            generate_retries: self.generate_retries,
+            allowed_peers: self.allowed_peers,
+            denied_peers: self.denied_peers,
+            idle_connection_timeout: self.idle_connection_timeout,
+            #[cfg(feature = "test-util")]
+            rng_seed: self.rng_seed,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
         }
     }
 }
@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as b64_url, Engine as Base64Engine};
+use dashmap::{mapref::one::Ref, DashMap};
+
+use crate::{swarm::SwarmError, DbData};
+
+#[derive(Clone, Debug, Default)]
+pub enum StorageBackend {
+    #[default]
+    Memory,
+    Disk(PathBuf),
+}
+
+pub(crate) struct Database {
+    backend: StorageBackend,
+    data: DashMap<Vec<u8>, DbData>,
+}
+
+impl Database {
+    pub(crate) fn new(backend: StorageBackend) -> Result<Self, SwarmError> {
+        let data = DashMap::new();
+        if let StorageBackend::Disk(path) = &backend {
+            fs::create_dir_all(path).map_err(|_| SwarmError::StorageError)?;
+            for entry in fs::read_dir(path).map_err(|_| SwarmError::StorageError)? {
+                let entry = entry.map_err(|_| SwarmError::StorageError)?;
+                let Some(key) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| b64_url.decode(name).ok())
+                else {
+                    continue;
+                };
+                let bytes = fs::read(entry.path()).map_err(|_| SwarmError::StorageError)?;
+                let value =
+                    bincode::deserialize::<DbData>(&bytes).map_err(|_| SwarmError::StorageError)?;
+                data.insert(key, value);
+            }
+        }
+        Ok(Self { backend, data })
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<Ref<'_, Vec<u8>, DbData>> {
+        self.data.get(key)
+    }
+
+    pub(crate) fn insert(&self, key: Vec<u8>, value: DbData) -> Result<(), SwarmError> {
+        if let StorageBackend::Disk(path) = &self.backend {
+            let bytes = bincode::serialize(&value).map_err(|_| SwarmError::StorageError)?;
+            let file_name = b64_url.encode(&key);
+            let tmp_path = path.join(format!(".{}.tmp", file_name));
+            write_private(&tmp_path, &bytes)?;
+            fs::rename(&tmp_path, path.join(file_name)).map_err(|_| SwarmError::StorageError)?;
+        }
+        self.data.insert(key, value);
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, key: &[u8]) -> Result<(), SwarmError> {
+        if let StorageBackend::Disk(path) = &self.backend {
+            let file_path = path.join(b64_url.encode(key));
+            if file_path.exists() {
+                fs::remove_file(file_path).map_err(|_| SwarmError::StorageError)?;
+            }
+        }
+        self.data.remove(key);
+        Ok(())
+    }
+
+    pub(crate) fn keys(&self) -> Vec<Vec<u8>> {
+        self.data.iter().map(|entry| entry.key().clone()).collect()
+    }
+}
+
+pub(crate) fn write_private(path: &Path, bytes: &[u8]) -> Result<(), SwarmError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|_| SwarmError::StorageError)?;
+    file.write_all(bytes)
+        .map_err(|_| SwarmError::StorageError)?;
+    file.sync_all().map_err(|_| SwarmError::StorageError)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use frost_ed25519::Identifier;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("frostore-db-{}", rand::random::<u64>()))
+    }
+
+    fn value() -> DbData {
+        DbData {
+            identifier: Some(Identifier::try_from(7).unwrap()),
+            key_package: None,
+            participants: None,
+            public_key_package: None,
+            signer_config: None,
+        }
+    }
+
+    #[test]
+    fn disk_values_survive_a_reopen() {
+        let path = temp_dir();
+        let database = Database::new(StorageBackend::Disk(path.clone())).unwrap();
+        database.insert(b"key".to_vec(), value()).unwrap();
+        drop(database);
+
+        let database = Database::new(StorageBackend::Disk(path.clone())).unwrap();
+        assert_eq!(database.get(b"key").unwrap().identifier, value().identifier);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn disk_remove_deletes_the_file() {
+        let path = temp_dir();
+        let database = Database::new(StorageBackend::Disk(path.clone())).unwrap();
+        database.insert(b"key".to_vec(), value()).unwrap();
+        let file_path = path.join(b64_url.encode(b"key"));
+        assert!(file_path.exists());
+
+        database.remove(b"key").unwrap();
+        assert!(!file_path.exists());
+        assert!(database.get(b"key").is_none());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn disk_load_skips_leftover_tmp_files() {
+        let path = temp_dir();
+        fs::create_dir_all(&path).unwrap();
+        // AN INSERT INTERRUPTED BEFORE ITS RENAME LEAVES ONLY THE TEMPORARY FILE
+        let tmp_path = path.join(format!(".{}.tmp", b64_url.encode(b"key")));
+        fs::write(&tmp_path, b"partial write").unwrap();
+
+        let database = Database::new(StorageBackend::Disk(path.clone())).unwrap();
+        assert!(database.keys().is_empty());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
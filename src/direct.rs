@@ -0,0 +1,245 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::FutureExt;
+use futures_timer::Delay;
+use libp2p::{
+    core::Endpoint,
+    gossipsub::TopicHash,
+    request_response::{
+        self, Message as ReqResMessage, OutboundFailure, OutboundRequestId, ResponseChannel,
+    },
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+        THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+
+use crate::DirectMsgData;
+
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+type Inner = request_response::cbor::Behaviour<DirectMsgData, Vec<u8>>;
+
+struct PendingRequest {
+    attempts: u32,
+    peer: PeerId,
+    request: DirectMsgData,
+}
+
+/// A message this node addressed to itself. Neither request-response nor gossipsub deliver those,
+/// so the main loop handles them once the current event is done.
+pub(crate) enum Loopback {
+    Message(TopicHash, Vec<u8>),
+    Request(Box<DirectMsgData>),
+}
+
+pub(crate) struct Behaviour {
+    inner: Inner,
+    local_peer_id: PeerId,
+    loopback: VecDeque<Loopback>,
+    pending: HashMap<OutboundRequestId, PendingRequest>,
+    retries: Vec<(Delay, PendingRequest)>,
+}
+
+impl Behaviour {
+    pub(crate) fn new(inner: Inner, local_peer_id: PeerId) -> Self {
+        Self {
+            inner,
+            local_peer_id,
+            loopback: VecDeque::new(),
+            pending: HashMap::new(),
+            retries: Vec::new(),
+        }
+    }
+
+    /// Returns `None` for a request to the local node, which is queued as a [`Loopback`] and
+    /// never answered.
+    pub(crate) fn send_request(
+        &mut self,
+        peer: &PeerId,
+        request: DirectMsgData,
+    ) -> Option<OutboundRequestId> {
+        if *peer == self.local_peer_id {
+            self.loopback
+                .push_back(Loopback::Request(Box::new(request)));
+            return None;
+        }
+        Some(self.send_attempt(PendingRequest {
+            attempts: 1,
+            peer: *peer,
+            request,
+        }))
+    }
+
+    pub(crate) fn loop_back(&mut self, topic: TopicHash, data: Vec<u8>) {
+        self.loopback.push_back(Loopback::Message(topic, data));
+    }
+
+    pub(crate) fn take_loopback(&mut self) -> Option<Loopback> {
+        self.loopback.pop_front()
+    }
+
+    pub(crate) fn send_response(
+        &mut self,
+        channel: ResponseChannel<Vec<u8>>,
+        response: Vec<u8>,
+    ) -> Result<(), Vec<u8>> {
+        self.inner.send_response(channel, response)
+    }
+
+    fn send_attempt(&mut self, pending: PendingRequest) -> OutboundRequestId {
+        let request_id = self
+            .inner
+            .send_request(&pending.peer, pending.request.clone());
+        self.pending.insert(request_id, pending);
+        request_id
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = <Inner as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = <Inner as NetworkBehaviour>::ToSwarm;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.inner.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        let mut index = 0;
+        while index < self.retries.len() {
+            if self.retries[index].0.poll_unpin(cx).is_ready() {
+                let (_, pending) = self.retries.swap_remove(index);
+                self.send_attempt(pending);
+            } else {
+                index += 1;
+            }
+        }
+        loop {
+            let Poll::Ready(event) = self.inner.poll(cx) else {
+                return Poll::Pending;
+            };
+            match event {
+                ToSwarm::GenerateEvent(request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                }) => {
+                    if let Some(pending) = self.pending.remove(&request_id) {
+                        // ONLY A FAILED DIAL SURELY NEVER DELIVERED THE REQUEST, AND LIVENESS
+                        // PINGS MUST FAIL WITHIN ONE REQUEST TIMEOUT
+                        let retryable = matches!(error, OutboundFailure::DialFailure)
+                            && !matches!(pending.request, DirectMsgData::Ping(_));
+                        if retryable && pending.attempts < MAX_ATTEMPTS {
+                            let mut delay =
+                                Delay::new(INITIAL_BACKOFF * 2u32.pow(pending.attempts - 1));
+                            let _ = delay.poll_unpin(cx);
+                            self.retries.push((
+                                delay,
+                                PendingRequest {
+                                    attempts: pending.attempts + 1,
+                                    ..pending
+                                },
+                            ));
+                            continue;
+                        }
+                    }
+                    return Poll::Ready(ToSwarm::GenerateEvent(
+                        request_response::Event::OutboundFailure {
+                            peer,
+                            request_id,
+                            error,
+                        },
+                    ));
+                }
+                ToSwarm::GenerateEvent(request_response::Event::Message {
+                    peer,
+                    message:
+                        ReqResMessage::Response {
+                            request_id,
+                            response,
+                        },
+                }) => {
+                    self.pending.remove(&request_id);
+                    return Poll::Ready(ToSwarm::GenerateEvent(request_response::Event::Message {
+                        peer,
+                        message: ReqResMessage::Response {
+                            request_id,
+                            response,
+                        },
+                    }));
+                }
+                event => return Poll::Ready(event),
+            }
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine as Base64Engine};
 use dashmap::{mapref::one::RefMut, DashMap};
@@ -12,15 +16,20 @@ use libp2p::{
     PeerId, Swarm as Libp2pSwarm, Swarm,
 };
 use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
 
-use crate::swarm::SwarmError;
+use crate::swarm::{SwarmError, TopicHashing};
 use crate::{
-    input::ReqGenerate, Behaviour, DbData, DirectMsgData, MessageData, QueryId, SignerConfig,
-    SwarmOutput,
+    database::Database,
+    input::ReqGenerate,
+    utils::{identifier_for, publish},
+    Behaviour, DbData, DirectMsgData, MessageData, QueryId, SignerConfig, SwarmOutput,
+    APP_TOPIC_PREFIX,
 };
 
 #[derive(Deserialize, Serialize)]
 pub(crate) enum GenerationMessage {
+    Abort,
     GenR1,
     GenR2(Identifier, Box<dkg::round1::Package>),
     GenFinal(Identifier, BTreeMap<Identifier, dkg::round2::Package>),
@@ -28,9 +37,13 @@ pub(crate) enum GenerationMessage {
 
 pub(crate) struct Generator {
     pub(crate) identifier: Identifier,
+    last_activity: Instant,
+    participants: BTreeMap<Identifier, PeerId>,
     pub(crate) propagation_source: Option<PeerId>,
+    query_id: QueryId,
     pub(crate) signer_config: SignerConfig,
     pub(crate) topic: TopicHash,
+    topic_hashing: TopicHashing,
     round1_secret_package: Option<dkg::round1::SecretPackage>,
     round1_packages: BTreeMap<Identifier, dkg::round1::Package>,
     round2_secret_package: Option<dkg::round2::SecretPackage>,
@@ -40,14 +53,21 @@ pub(crate) struct Generator {
 impl Generator {
     pub(crate) fn new(
         identifier: Identifier,
+        local_peer_id: PeerId,
+        propagation_source: PeerId,
+        query_id: QueryId,
         signer_config: SignerConfig,
-        topic: TopicHash,
+        topic_hashing: TopicHashing,
     ) -> Self {
         Self {
             identifier,
-            propagation_source: None,
+            last_activity: Instant::now(),
+            participants: BTreeMap::from([(identifier, local_peer_id)]),
+            propagation_source: Some(propagation_source),
+            topic: topic_hashing.hash(&query_id),
+            query_id,
             signer_config,
-            topic,
+            topic_hashing,
             round1_secret_package: None,
             round1_packages: BTreeMap::new(),
             round2_secret_package: None,
@@ -75,10 +95,7 @@ impl Generator {
             Box::new(round1_package),
         )))
         .map_err(|_| SwarmError::MessageProcessingError)?;
-        let _ = swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topic.clone(), send_message);
+        publish(swarm, self.topic.clone(), send_message);
         Ok(())
     }
 
@@ -104,10 +121,7 @@ impl Generator {
             GenerationMessage::GenFinal(self.identifier, round2_packages),
         ))
         .map_err(|_| SwarmError::MessageProcessingError)?;
-        let _ = swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topic.clone(), send_message);
+        publish(swarm, self.topic.clone(), send_message);
         Ok(())
     }
 
@@ -120,6 +134,18 @@ impl Generator {
         Ok(self.round2_packages.len())
     }
 
+    // PACKAGES FROM FASTER PEERS CAN ARRIVE BEFORE THIS NODE HAS STARTED THE ROUND
+    fn round1_complete(&self) -> bool {
+        self.round1_secret_package.is_some()
+            && self.round2_secret_package.is_none()
+            && self.round1_packages.len() + 1 >= self.signer_config.max_signers as usize
+    }
+
+    fn round2_complete(&self) -> bool {
+        self.round2_secret_package.is_some()
+            && self.round2_packages.len() + 1 >= self.signer_config.max_signers as usize
+    }
+
     pub(crate) fn gen_final(&self) -> Result<(PublicKeyPackage, KeyPackage), SwarmError> {
         let (key_package, pubkey_package) = dkg::part3(
             &self
@@ -137,50 +163,123 @@ impl Generator {
 pub(crate) fn gen_start(
     generator_db: &Arc<DashMap<QueryId, Generator>>,
     swarm: &mut Swarm<Behaviour>,
+    propagation_source: PeerId,
     query_id: QueryId,
     signer_config: SignerConfig,
-    participant_id: u16,
+    topic_hashing: TopicHashing,
 ) -> Result<(), SwarmError> {
+    if !signer_config.is_valid() || query_id.starts_with(APP_TOPIC_PREFIX) {
+        return Err(SwarmError::ConfigurationError);
+    }
+    debug!(query_id = %query_id, requester = %propagation_source, "joining generation");
     let _ = swarm.behaviour_mut().kad.bootstrap();
     let generator = Generator::new(
-        Identifier::try_from(participant_id).map_err(|_| SwarmError::MessageProcessingError)?,
+        identifier_for(swarm.local_peer_id())?,
+        *swarm.local_peer_id(),
+        propagation_source,
+        query_id.clone(),
         signer_config.clone(),
-        TopicHash::from_raw(&query_id),
+        topic_hashing,
     );
     generator_db.insert(query_id.clone(), generator);
-    let _ = swarm
-        .behaviour_mut()
-        .gossipsub
-        .subscribe(&IdentTopic::new(query_id));
+    topic_hashing.subscribe(&mut swarm.behaviour_mut().gossipsub, &query_id);
     Ok(())
 }
 
 pub(crate) fn handle_generation_msg(
-    database: Arc<DashMap<Vec<u8>, DbData>>,
+    output: UnboundedSender<SwarmOutput>,
+    database: Arc<Database>,
     swarm: &mut Libp2pSwarm<Behaviour>,
     generator_db: Arc<DashMap<QueryId, Generator>>,
     message: GenerationMessage,
     propagation_source: PeerId,
     topic: TopicHash,
 ) -> Result<(), SwarmError> {
-    let generator = generator_db
-        .get_mut(&topic.to_string())
+    let query_id = generator_db
+        .iter()
+        .find(|entry| entry.topic == topic)
+        .map(|entry| entry.key().clone())
         .ok_or(SwarmError::DatabaseError)?;
+    let active_generator = || {
+        let mut generator = generator_db
+            .get_mut(&query_id)
+            .ok_or(SwarmError::DatabaseError)?;
+        generator.last_activity = Instant::now();
+        Ok::<_, SwarmError>(generator)
+    };
     match message {
-        GenerationMessage::GenR1 => handle_r1_generation(swarm, generator, propagation_source)?,
+        GenerationMessage::Abort => {
+            handle_abort_generation(swarm, &generator_db, propagation_source, &query_id)?
+        }
+        GenerationMessage::GenR1 => {
+            let mut generator = active_generator()?;
+            handle_r1_generation(swarm, &mut generator, propagation_source)?;
+            advance_generation(output, database, swarm, &generator_db, generator)?
+        }
         GenerationMessage::GenR2(identifier, package) => {
-            handle_r2_generation(swarm, generator, identifier, *package)?
+            let mut generator = active_generator()?;
+            handle_r2_generation(&mut generator, propagation_source, identifier, *package)?;
+            advance_generation(output, database, swarm, &generator_db, generator)?
         }
         GenerationMessage::GenFinal(received_identifier, packages) => {
-            handle_final_generation(database, swarm, generator, received_identifier, packages)?
+            let mut generator = active_generator()?;
+            handle_final_generation(
+                &mut generator,
+                propagation_source,
+                received_identifier,
+                packages,
+            )?;
+            advance_generation(output, database, swarm, &generator_db, generator)?
         }
     }
     Ok(())
 }
 
-fn handle_r1_generation(
+fn advance_generation(
+    output: UnboundedSender<SwarmOutput>,
+    database: Arc<Database>,
     swarm: &mut Libp2pSwarm<Behaviour>,
+    generator_db: &Arc<DashMap<QueryId, Generator>>,
     mut generator: RefMut<QueryId, Generator>,
+) -> Result<(), SwarmError> {
+    if generator.round1_complete() {
+        generator.gen_r2(swarm)?;
+    }
+    if !generator.round2_complete() {
+        return Ok(());
+    }
+    finish_generation(output, database, swarm, &mut generator)?;
+    let query_id = generator.query_id.clone();
+    let topic_hashing = generator.topic_hashing;
+    drop(generator);
+    generator_db.remove(&query_id);
+    topic_hashing.unsubscribe(&mut swarm.behaviour_mut().gossipsub, &query_id);
+    Ok(())
+}
+
+fn handle_abort_generation(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generator_db: &Arc<DashMap<QueryId, Generator>>,
+    propagation_source: PeerId,
+    query_id: &QueryId,
+) -> Result<(), SwarmError> {
+    let from_requester = generator_db
+        .get(query_id)
+        .is_some_and(|generator| generator.propagation_source == Some(propagation_source));
+    if !from_requester {
+        return Err(SwarmError::InvalidPeer);
+    }
+    if let Some((_, generator)) = generator_db.remove(query_id) {
+        generator
+            .topic_hashing
+            .unsubscribe(&mut swarm.behaviour_mut().gossipsub, query_id);
+    }
+    Ok(())
+}
+
+fn handle_r1_generation(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generator: &mut Generator,
     propagation_source: PeerId,
 ) -> Result<(), SwarmError> {
     generator.gen_r1(swarm, propagation_source)?;
@@ -188,78 +287,110 @@ fn handle_r1_generation(
 }
 
 fn handle_r2_generation(
-    swarm: &mut Libp2pSwarm<Behaviour>,
-    mut generator: RefMut<QueryId, Generator>,
+    generator: &mut Generator,
+    propagation_source: PeerId,
     identifier: Identifier,
     package: dkg::round1::Package,
 ) -> Result<(), SwarmError> {
-    let db_length = generator.insert_r1(identifier, package)?;
-    if db_length + 1 >= generator.signer_config.max_signers as usize {
-        generator.gen_r2(swarm)?;
+    // AN AUTHOR CAN ONLY SPEAK FOR THE IDENTIFIER ITS PEER ID DERIVES
+    if identifier != identifier_for(&propagation_source)? {
+        return Err(SwarmError::InvalidPeer);
     }
+    // THIS NODE'S OWN PACKAGE LOOPS BACK TO IT
+    if identifier == generator.identifier {
+        return Ok(());
+    }
+    generator
+        .participants
+        .insert(identifier, propagation_source);
+    generator.insert_r1(identifier, package)?;
     Ok(())
 }
 
 fn handle_final_generation(
-    database: Arc<DashMap<Vec<u8>, DbData>>,
-    swarm: &mut Libp2pSwarm<Behaviour>,
-    mut generator: RefMut<QueryId, Generator>,
+    generator: &mut Generator,
+    propagation_source: PeerId,
     received_identifier: Identifier,
     mut packages: BTreeMap<Identifier, dkg::round2::Package>,
 ) -> Result<(), SwarmError> {
+    if received_identifier != identifier_for(&propagation_source)? {
+        return Err(SwarmError::InvalidPeer);
+    }
+    if received_identifier == generator.identifier {
+        return Ok(());
+    }
     let round2_package = packages
         .remove(&generator.identifier)
         .ok_or(SwarmError::DatabaseError)?;
-    let db_length = generator.insert_r2(received_identifier, round2_package)?;
-    if db_length + 1 >= generator.signer_config.max_signers as usize {
-        let (pubkey_package, key_package) = generator.gen_final()?;
-        let new_topic = b64.encode(pubkey_package.verifying_key().serialize());
-        let _ = swarm
-            .behaviour_mut()
-            .gossipsub
-            .subscribe(&IdentTopic::new(new_topic));
-        return_gen(swarm, &mut generator, pubkey_package.clone())?;
-        database.insert(
-            pubkey_package.verifying_key().serialize().to_vec(),
-            DbData {
-                identifier: Some(generator.identifier),
-                key_package: Some(key_package),
-                public_key_package: Some(pubkey_package),
-                signer_config: Some(generator.signer_config.clone()),
-            },
-        );
-    }
+    generator.insert_r2(received_identifier, round2_package)?;
+    Ok(())
+}
+
+fn finish_generation(
+    mut output: UnboundedSender<SwarmOutput>,
+    database: Arc<Database>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generator: &mut Generator,
+) -> Result<(), SwarmError> {
+    let (pubkey_package, key_package) = generator.gen_final()?;
+    let new_topic = b64.encode(pubkey_package.verifying_key().serialize());
+    let _ = swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&IdentTopic::new(new_topic));
+    return_gen(swarm, generator, pubkey_package.clone())?;
+    let verifying_key = *pubkey_package.verifying_key();
+    database.insert(
+        verifying_key.serialize().to_vec(),
+        DbData {
+            identifier: Some(generator.identifier),
+            key_package: Some(key_package),
+            participants: Some(generator.participants.clone()),
+            public_key_package: Some(pubkey_package),
+            signer_config: Some(generator.signer_config.clone()),
+        },
+    )?;
+    let _ = output.start_send(SwarmOutput::JoinedGroup(
+        verifying_key,
+        generator.signer_config.clone(),
+        generator.participants.clone(),
+    ));
     Ok(())
 }
 
 pub(crate) fn send_final_gen(
     mut output: UnboundedSender<SwarmOutput>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
     generation_requester_db: &Arc<DashMap<QueryId, ReqGenerate>>,
-    database: Arc<DashMap<Vec<u8>, DbData>>,
+    peer: PeerId,
     query_id: QueryId,
     pubkey_package: PublicKeyPackage,
 ) -> Result<(), SwarmError> {
-    let _ = output.start_send(SwarmOutput::Generation(
-        query_id.clone(),
-        *pubkey_package.verifying_key(),
-    ));
-    if !generation_requester_db.contains_key(&query_id) {
+    let Some(mut generation_requester) = generation_requester_db.get_mut(&query_id) else {
+        return Ok(());
+    };
+    // EVERY PARTICIPANT, THE REQUESTER INCLUDED, REPORTS ONCE IT HAS STORED ITS SHARE
+    let count = generation_requester.insert_result(peer, pubkey_package)?;
+    if count < generation_requester.signer_config.max_signers as usize {
         return Ok(());
     }
-    let generation_requester = generation_requester_db
+    drop(generation_requester);
+    let (_, generation_requester) = generation_requester_db
         .remove(&query_id)
-        .ok_or(SwarmError::DatabaseError)?
-        .1;
-    database.insert(
-        pubkey_package.verifying_key().serialize().to_vec(),
-        DbData {
-            identifier: None,
-            key_package: None,
-            public_key_package: None,
-            signer_config: Some(generation_requester.signer_config.clone()),
-        },
-    );
-    generation_requester.send_response(*pubkey_package.verifying_key())?;
+        .ok_or(SwarmError::DatabaseError)?;
+    generation_requester.unsubscribe(swarm)?;
+    let mut results = generation_requester.results.values();
+    let pubkey_package = results.next().ok_or(SwarmError::DatabaseError)?.clone();
+    if results.any(|result| *result != pubkey_package) {
+        warn!(query_id = %generation_requester.origin, "participants disagree on the group key");
+        return generation_requester.send_response(Err(SwarmError::GenerationError));
+    }
+    info!(query_id = %generation_requester.origin, "generation complete");
+    let _ = output.start_send(SwarmOutput::Generation(
+        generation_requester.origin.clone(),
+        *pubkey_package.verifying_key(),
+    ));
+    generation_requester.send_response(Ok(pubkey_package))?;
     Ok(())
 }
 
@@ -272,7 +403,60 @@ fn return_gen(
         &generator
             .propagation_source
             .ok_or(SwarmError::DatabaseError)?,
-        DirectMsgData::ReturnGen(generator.topic.to_string(), pubkey_package),
+        DirectMsgData::ReturnGen(generator.query_id.clone(), pubkey_package),
     );
     Ok(())
 }
+
+pub(crate) fn expire_generators(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    round_timeout: Duration,
+    generator_db: &Arc<DashMap<QueryId, Generator>>,
+) -> Result<(), SwarmError> {
+    let expired = generator_db
+        .iter()
+        .filter(|entry| entry.last_activity.elapsed() > round_timeout)
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for query_id in expired {
+        if let Some((_, generator)) = generator_db.remove(&query_id) {
+            generator
+                .topic_hashing
+                .unsubscribe(&mut swarm.behaviour_mut().gossipsub, &query_id);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round1_package(identifier: Identifier) -> dkg::round1::Package {
+        dkg::part1(identifier, 3, 2, rand::rngs::OsRng).unwrap().1
+    }
+
+    #[test]
+    fn round1_package_must_match_its_author() {
+        let local = PeerId::random();
+        let honest = PeerId::random();
+        let forger = PeerId::random();
+        let mut generator = Generator::new(
+            identifier_for(&local).unwrap(),
+            local,
+            PeerId::random(),
+            "query".to_string(),
+            SignerConfig::new(2, 3),
+            TopicHashing::default(),
+        );
+        let claimed = identifier_for(&honest).unwrap();
+        assert!(matches!(
+            handle_r2_generation(&mut generator, forger, claimed, round1_package(claimed)),
+            Err(SwarmError::InvalidPeer)
+        ));
+        assert!(generator.round1_packages.is_empty());
+        assert!(!generator.participants.contains_key(&claimed));
+        handle_r2_generation(&mut generator, honest, claimed, round1_package(claimed)).unwrap();
+        assert_eq!(generator.participants.get(&claimed), Some(&honest));
+    }
+}
@@ -1,58 +1,142 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
 use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine as Base64Engine};
 use dashmap::DashMap;
-use frost_ed25519::{round1, Identifier, Signature, SigningPackage, VerifyingKey};
-use futures::channel::oneshot;
-use libp2p::{gossipsub::TopicHash, PeerId, Swarm as Libp2pSwarm};
+use frost_ed25519::{
+    keys::{KeyPackage, PublicKeyPackage},
+    round1, round2, Identifier, Signature, SigningPackage, VerifyingKey,
+};
+use futures::channel::{mpsc::UnboundedSender, oneshot};
+use libp2p::{
+    core::transport::ListenerId,
+    gossipsub::{IdentTopic, TopicHash},
+    request_response::OutboundRequestId,
+    swarm::{dial_opts::DialOpts, ConnectionId},
+    PeerId, Swarm as Libp2pSwarm,
+};
 use rand::Rng;
+use tracing::{debug, info, info_span, warn};
 
-use crate::swarm::SwarmError;
+use crate::swarm::{
+    new_query_id, GroupBundle, GroupStatus, HealthSnapshot, Round, SwarmError, SwarmOutput,
+    TimeoutConfig, TopicHashing, TopologySnapshot,
+};
 use crate::{
-    utils::{get_peers_list, peerid_from_multiaddress},
+    database::Database,
+    presign::{take_presigned, PresignMessage, Presigned, MAX_PRESIGN_BATCH},
+    reshare::ReshareConfig,
+    utils::{get_peers_list, identifier_for, peerid_from_multiaddress, publish},
     Behaviour, DbData, DirectMsgData, GenerationMessage, MessageData, Multiaddr, QueryId,
-    SignerConfig, SigningMessage,
+    SignerConfig, SigningMessage, APP_TOPIC_PREFIX,
 };
 
 pub(crate) struct ReqGenerate {
+    attempt: u32,
+    pub(crate) discovered: bool,
     peers: Vec<PeerId>,
+    round1_responses: BTreeSet<Identifier>,
+    round2_responses: BTreeSet<Identifier>,
     selected_peers: Vec<PeerId>,
-    peer_response_count: usize,
+    responded_peers: BTreeSet<PeerId>,
+    pub(crate) results: BTreeMap<PeerId, PublicKeyPackage>,
+    // THE ID THE CALLER KNOWS, query_id DIFFERS FROM IT ON RETRIES
+    pub(crate) origin: QueryId,
     pub(crate) query_id: QueryId,
-    response_channel: oneshot::Sender<VerifyingKey>,
+    response_channel: oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
+    retries: u32,
     pub(crate) signer_config: SignerConfig,
+    started: Instant,
+    pub(crate) topic: TopicHash,
+    topic_hashing: TopicHashing,
 }
 
 impl ReqGenerate {
     pub(crate) fn new(
-        peers: Vec<PeerId>,
+        mut peers: Vec<PeerId>,
         query_id: QueryId,
-        response_channel: oneshot::Sender<VerifyingKey>,
+        response_channel: oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
         signer_config: SignerConfig,
+        topic_hashing: TopicHashing,
+        retries: u32,
     ) -> Self {
+        let mut seen = BTreeSet::new();
+        peers.retain(|peer| seen.insert(*peer));
         Self {
+            attempt: 0,
+            discovered: false,
             peers,
+            round1_responses: BTreeSet::new(),
+            round2_responses: BTreeSet::new(),
             selected_peers: Vec::new(),
-            peer_response_count: 0,
+            responded_peers: BTreeSet::new(),
+            results: BTreeMap::new(),
+            topic: topic_hashing.hash(&query_id),
+            origin: query_id.clone(),
             query_id,
             response_channel,
+            retries,
             signer_config,
+            started: Instant::now(),
+            topic_hashing,
         }
     }
 
+    // THE NEXT ATTEMPT KEEPS THE CALLER'S CHANNEL AND ID BUT NOTHING ELSE OF THIS RUN
+    fn into_retry(self, peers: Vec<PeerId>, query_id: QueryId) -> Self {
+        let mut retry = Self::new(
+            peers,
+            query_id,
+            self.response_channel,
+            self.signer_config,
+            self.topic_hashing,
+            self.retries,
+        );
+        retry.attempt = self.attempt + 1;
+        retry.origin = self.origin;
+        retry
+    }
+
+    // THE REQUESTER TAKES ONE OF THE SEATS ITSELF
+    pub(crate) fn has_enough_peers(&self) -> bool {
+        self.peers.len() + 1 >= self.signer_config.max_signers as usize
+    }
+
+    // NONE ONCE THE ROUTING TABLE HAS NO UNSELECTED CANDIDATES LEFT
+    fn take_random_peer(&mut self) -> Option<PeerId> {
+        if self.peers.is_empty() {
+            return None;
+        }
+        Some(
+            self.peers
+                .remove(rand::thread_rng().gen_range(0..self.peers.len())),
+        )
+    }
+
     pub(crate) fn gen_r1(&mut self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
-        for count in 1..=self.signer_config.max_signers {
+        if !self.has_enough_peers() {
+            return Err(SwarmError::InsufficientPeers);
+        }
+        self.topic_hashing
+            .subscribe(&mut swarm.behaviour_mut().gossipsub, &self.query_id);
+        // THE LOCAL GENERATOR SHARES THE REQUESTER'S SUBSCRIPTION, SO NO EVENT ANNOUNCES IT
+        let local_peer_id = *swarm.local_peer_id();
+        self.selected_peers.push(local_peer_id);
+        self.responded_peers.insert(local_peer_id);
+        for _ in 1..self.signer_config.max_signers {
             let peer = self
-                .peers
-                .remove(rand::thread_rng().gen_range(0..self.peers.len()));
+                .take_random_peer()
+                .ok_or(SwarmError::InsufficientPeers)?;
             self.selected_peers.push(peer);
+        }
+        // SEND_REQUEST ONLY QUEUES, SO EVERY SELECTED PEER IS INVITED CONCURRENTLY
+        for peer in &self.selected_peers {
             let _ = swarm.behaviour_mut().req_res.send_request(
-                &peer,
-                DirectMsgData::GenStart(
-                    self.query_id.to_string(),
-                    self.signer_config.clone(),
-                    count,
-                ),
+                peer,
+                DirectMsgData::GenStart(self.query_id.to_string(), self.signer_config.clone()),
             );
         }
         Ok(())
@@ -62,21 +146,103 @@ impl ReqGenerate {
         if !self.selected_peers.contains(&peer) {
             return Err(SwarmError::InvalidPeer);
         }
-        self.peer_response_count += 1;
-        Ok(self.peer_response_count)
+        self.responded_peers.insert(peer);
+        Ok(self.responded_peers.len())
+    }
+
+    pub(crate) fn insert_result(
+        &mut self,
+        peer: PeerId,
+        pubkey_package: PublicKeyPackage,
+    ) -> Result<usize, SwarmError> {
+        if !self.selected_peers.contains(&peer) {
+            return Err(SwarmError::InvalidPeer);
+        }
+        self.results.insert(peer, pubkey_package);
+        Ok(self.results.len())
+    }
+
+    pub(crate) fn is_selected(&self, peer: &PeerId) -> bool {
+        self.selected_peers.contains(peer)
+    }
+
+    pub(crate) fn replace_peer(
+        &mut self,
+        peer: PeerId,
+        swarm: &mut Libp2pSwarm<Behaviour>,
+    ) -> Result<bool, SwarmError> {
+        let Some(index) = self
+            .selected_peers
+            .iter()
+            .position(|selected| *selected == peer)
+        else {
+            return Ok(true);
+        };
+        self.responded_peers.remove(&peer);
+        let Some(replacement) = self.take_random_peer() else {
+            return Ok(false);
+        };
+        self.selected_peers[index] = replacement;
+        let _ = swarm.behaviour_mut().req_res.send_request(
+            &replacement,
+            DirectMsgData::GenStart(self.query_id.to_string(), self.signer_config.clone()),
+        );
+        Ok(true)
     }
 
     pub(crate) fn gen_r2(&mut self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        self.discovered = true;
         let send_message = bincode::serialize(&MessageData::Generation(GenerationMessage::GenR1))
             .map_err(|_| SwarmError::MessageProcessingError)?;
-        let _ = swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(TopicHash::from_raw(self.query_id.to_string()), send_message);
+        publish(swarm, self.topic.clone(), send_message);
         Ok(())
     }
 
-    pub(crate) fn send_response(self, response: VerifyingKey) -> Result<(), SwarmError> {
+    pub(crate) fn insert_progress(
+        &mut self,
+        message: &GenerationMessage,
+    ) -> Result<Option<(Round, usize)>, SwarmError> {
+        let progress = match message {
+            GenerationMessage::GenR2(identifier, _) => {
+                self.round1_responses.insert(*identifier);
+                Some((Round::Round1, self.round1_responses.len()))
+            }
+            GenerationMessage::GenFinal(identifier, _) => {
+                self.round2_responses.insert(*identifier);
+                Some((Round::Round2, self.round2_responses.len()))
+            }
+            _ => None,
+        };
+        Ok(progress)
+    }
+
+    pub(crate) fn is_expired(&self, timeouts: &TimeoutConfig) -> bool {
+        let elapsed = self.started.elapsed();
+        elapsed > timeouts.overall || (!self.discovered && elapsed > timeouts.discovery)
+    }
+
+    pub(crate) fn abort(&self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        let send_message = bincode::serialize(&MessageData::Generation(GenerationMessage::Abort))
+            .map_err(|_| SwarmError::MessageProcessingError)?;
+        publish(swarm, self.topic.clone(), send_message);
+        self.unsubscribe(swarm)?;
+        Ok(())
+    }
+
+    pub(crate) fn unsubscribe(&self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        self.topic_hashing
+            .unsubscribe(&mut swarm.behaviour_mut().gossipsub, &self.query_id);
+        Ok(())
+    }
+
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.response_channel.is_canceled()
+    }
+
+    pub(crate) fn send_response(
+        self,
+        response: Result<PublicKeyPackage, SwarmError>,
+    ) -> Result<(), SwarmError> {
         let _ = self.response_channel.send(response);
         Ok(())
     }
@@ -84,12 +250,22 @@ impl ReqGenerate {
 
 pub(crate) struct ReqSign {
     commitments_db: BTreeMap<Identifier, round1::SigningCommitments>,
-    finished: bool,
+    commitment_peers: BTreeMap<Identifier, PeerId>,
+    pub(crate) contextual: bool,
+    pub(crate) finished: bool,
+    last_ping: Instant,
     pub(crate) message: Vec<u8>,
+    pings: HashMap<OutboundRequestId, PeerId>,
     pub(crate) pubkey: Vec<u8>,
+    pub(crate) public_key_package: PublicKeyPackage,
     query_id: QueryId,
-    response_channel: oneshot::Sender<Signature>,
+    rejected_results: BTreeSet<PeerId>,
+    response_channel: oneshot::Sender<Result<Signature, SwarmError>>,
+    pub(crate) shares: BTreeMap<Identifier, round2::SignatureShare>,
     pub(crate) signer_config: SignerConfig,
+    pub(crate) signers: Option<BTreeSet<PeerId>>,
+    pub(crate) signing_package: Option<SigningPackage>,
+    pub(crate) started: Instant,
 }
 
 impl ReqSign {
@@ -97,27 +273,49 @@ impl ReqSign {
         message: Vec<u8>,
         query_id: QueryId,
         pubkey: Vec<u8>,
-        response_channel: oneshot::Sender<Signature>,
+        public_key_package: PublicKeyPackage,
+        response_channel: oneshot::Sender<Result<Signature, SwarmError>>,
         signer_config: SignerConfig,
+        signers: Option<BTreeSet<PeerId>>,
     ) -> Self {
         Self {
             commitments_db: BTreeMap::new(),
+            commitment_peers: BTreeMap::new(),
+            contextual: false,
             finished: false,
+            last_ping: Instant::now(),
             message,
+            pings: HashMap::new(),
             pubkey,
+            public_key_package,
             query_id,
+            rejected_results: BTreeSet::new(),
             response_channel,
+            shares: BTreeMap::new(),
             signer_config,
+            signers,
+            signing_package: None,
+            started: Instant::now(),
         }
     }
 
     pub(crate) fn sign_r1(&self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        if let Some(signers) = &self.signers {
+            for peer in signers {
+                let _ = swarm.behaviour_mut().req_res.send_request(
+                    peer,
+                    DirectMsgData::SignRequest(self.query_id.to_string(), self.pubkey.clone()),
+                );
+            }
+            return Ok(());
+        }
         let send_message = bincode::serialize(&MessageData::Signing(SigningMessage::SignR1(
             self.query_id.to_string(),
         )))
         .map_err(|_| SwarmError::MessageProcessingError)?;
-        let _ = swarm.behaviour_mut().gossipsub.publish(
-            TopicHash::from_raw(b64.encode(self.pubkey.clone())),
+        publish(
+            swarm,
+            TopicHash::from_raw(b64.encode(&self.pubkey)),
             send_message,
         );
         Ok(())
@@ -125,35 +323,396 @@ impl ReqSign {
 
     pub(crate) fn insert_commitments(
         &mut self,
+        peer: PeerId,
         participant_identifier: Identifier,
         commitments: round1::SigningCommitments,
     ) -> Result<usize, SwarmError> {
-        self.commitments_db
-            .insert(participant_identifier, commitments);
+        if !self.finished {
+            self.commitments_db
+                .insert(participant_identifier, commitments);
+            self.commitment_peers.insert(participant_identifier, peer);
+        }
         Ok(self.commitments_db.len())
     }
 
+    pub(crate) fn sign_presigned(
+        &mut self,
+        swarm: &mut Libp2pSwarm<Behaviour>,
+        presigned: Presigned,
+    ) -> Result<(), SwarmError> {
+        self.commitments_db = presigned.commitments;
+        self.commitment_peers = presigned.peers;
+        self.finished = true;
+        let signing_package = SigningPackage::new(self.commitments_db.clone(), &self.message);
+        self.signing_package = Some(signing_package.clone());
+        let send_message = bincode::serialize(&MessageData::Presigning(PresignMessage::Sign(
+            self.query_id.to_string(),
+            presigned.session_id,
+            Box::new(signing_package),
+            self.contextual,
+        )))
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+        publish(
+            swarm,
+            TopicHash::from_raw(b64.encode(&self.pubkey)),
+            send_message,
+        );
+        Ok(())
+    }
+
+    pub(crate) fn insert_share(
+        &mut self,
+        identifier: Identifier,
+        share: round2::SignatureShare,
+    ) -> bool {
+        let expected = self
+            .signing_package
+            .as_ref()
+            .is_some_and(|signing_package| {
+                signing_package
+                    .signing_commitments()
+                    .contains_key(&identifier)
+            });
+        expected && self.shares.insert(identifier, share).is_none()
+    }
+
+    pub(crate) fn participants(&self) -> BTreeMap<Identifier, PeerId> {
+        self.commitment_peers.clone()
+    }
+
+    // ONLY THE SIGNERS IN THE SIGNING PACKAGE AGGREGATE AND RETURN A RESULT
+    pub(crate) fn is_signer(&self, peer: &PeerId) -> bool {
+        self.finished && self.commitment_peers.values().any(|signer| signer == peer)
+    }
+
+    // TRUE ONCE EVERY SIGNER HAS RETURNED AN INVALID RESULT
+    pub(crate) fn reject_result(&mut self, peer: PeerId) -> bool {
+        self.rejected_results.insert(peer);
+        self.commitment_peers
+            .values()
+            .all(|signer| self.rejected_results.contains(signer))
+    }
+
     pub(crate) fn sign_r2(&mut self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
         if self.finished {
             return Ok(());
         }
         self.finished = true;
         let signing_package = SigningPackage::new(self.commitments_db.clone(), &self.message);
+        self.signing_package = Some(signing_package.clone());
         let send_message = bincode::serialize(&MessageData::Signing(SigningMessage::SignR2(
             self.query_id.to_string(),
-            signing_package
-                .serialize()
-                .map_err(|_| SwarmError::MessageProcessingError)?,
+            Box::new(signing_package),
+            self.contextual,
+        )))
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+        publish(
+            swarm,
+            TopicHash::from_raw(b64.encode(&self.pubkey)),
+            send_message,
+        );
+        Ok(())
+    }
+
+    pub(crate) fn is_expired(&self, timeouts: &TimeoutConfig) -> bool {
+        let elapsed = self.started.elapsed();
+        elapsed > timeouts.overall || (!self.finished && elapsed > timeouts.discovery)
+    }
+
+    pub(crate) fn ping(&mut self, interval: Duration, swarm: &mut Libp2pSwarm<Behaviour>) {
+        if !self.finished || self.last_ping.elapsed() < interval {
+            return;
+        }
+        self.last_ping = Instant::now();
+        let outstanding = self
+            .commitment_peers
+            .iter()
+            .filter(|(identifier, _)| !self.shares.contains_key(identifier))
+            .map(|(_, peer)| *peer)
+            .collect::<Vec<_>>();
+        for peer in outstanding {
+            // THE LOCAL SIGNER IS NEVER PINGED, IT CANNOT DROP OUT OF ITS OWN REQUEST
+            if let Some(request_id) = swarm
+                .behaviour_mut()
+                .req_res
+                .send_request(&peer, DirectMsgData::Ping(self.query_id.to_string()))
+            {
+                self.pings.insert(request_id, peer);
+            }
+        }
+    }
+
+    pub(crate) fn abort(&self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        let send_message = bincode::serialize(&MessageData::Signing(SigningMessage::Abort(
+            self.query_id.to_string(),
         )))
         .map_err(|_| SwarmError::MessageProcessingError)?;
-        let _ = swarm.behaviour_mut().gossipsub.publish(
-            TopicHash::from_raw(b64.encode(self.pubkey.clone())),
+        publish(
+            swarm,
+            TopicHash::from_raw(b64.encode(&self.pubkey)),
             send_message,
         );
         Ok(())
     }
 
-    pub(crate) fn send_response(self, response: Signature) -> Result<(), SwarmError> {
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.response_channel.is_canceled()
+    }
+
+    pub(crate) fn send_response(
+        self,
+        response: Result<Signature, SwarmError>,
+    ) -> Result<(), SwarmError> {
+        let _ = self.response_channel.send(response);
+        Ok(())
+    }
+}
+
+pub(crate) struct ReqPresign {
+    pub(crate) count: u16,
+    pub(crate) pubkey: Vec<u8>,
+    query_id: QueryId,
+    pub(crate) responses: BTreeMap<Identifier, (PeerId, Vec<round1::SigningCommitments>)>,
+    response_channel: oneshot::Sender<Result<usize, SwarmError>>,
+    signer_config: SignerConfig,
+    pub(crate) started: Instant,
+}
+
+impl ReqPresign {
+    pub(crate) fn new(
+        count: u16,
+        pubkey: Vec<u8>,
+        query_id: QueryId,
+        response_channel: oneshot::Sender<Result<usize, SwarmError>>,
+        signer_config: SignerConfig,
+    ) -> Self {
+        Self {
+            count,
+            pubkey,
+            query_id,
+            responses: BTreeMap::new(),
+            response_channel,
+            signer_config,
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn request(&self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        let send_message = bincode::serialize(&MessageData::Presigning(PresignMessage::Request(
+            self.query_id.to_string(),
+            self.count,
+        )))
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+        publish(
+            swarm,
+            TopicHash::from_raw(b64.encode(&self.pubkey)),
+            send_message,
+        );
+        Ok(())
+    }
+
+    pub(crate) fn insert_commitments(
+        &mut self,
+        peer: PeerId,
+        identifier: Identifier,
+        commitments: Vec<round1::SigningCommitments>,
+    ) -> usize {
+        self.responses.insert(identifier, (peer, commitments));
+        self.responses.len()
+    }
+
+    // MATCHES THE NUMBER OF COMMITMENTS A GOSSIPED SIGNING WAITS FOR
+    pub(crate) fn threshold(&self) -> usize {
+        self.signer_config.min_signers as usize + 1
+    }
+
+    pub(crate) fn is_expired(&self, timeouts: &TimeoutConfig) -> bool {
+        self.started.elapsed() > timeouts.discovery
+    }
+
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.response_channel.is_canceled()
+    }
+
+    pub(crate) fn send_response(
+        self,
+        response: Result<usize, SwarmError>,
+    ) -> Result<(), SwarmError> {
+        let _ = self.response_channel.send(response);
+        Ok(())
+    }
+}
+
+pub(crate) struct ReqReshare {
+    pub(crate) config: ReshareConfig,
+    dealing: bool,
+    members: BTreeSet<PeerId>,
+    min_dealers: usize,
+    pings: HashMap<OutboundRequestId, (Identifier, PeerId)>,
+    query_id: QueryId,
+    pub(crate) responses: BTreeMap<PeerId, PublicKeyPackage>,
+    response_channel: oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
+    started: Instant,
+}
+
+impl ReqReshare {
+    pub(crate) fn new(
+        data: &DbData,
+        query_id: QueryId,
+        response_channel: oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
+        signer_config: SignerConfig,
+        new_peers: Vec<PeerId>,
+    ) -> Result<Self, SwarmError> {
+        let mut participants = data.participants.clone().ok_or(SwarmError::DatabaseError)?;
+        let old_config = data
+            .signer_config
+            .clone()
+            .ok_or(SwarmError::DatabaseError)?;
+        for peer in new_peers {
+            let identifier = identifier_for(&peer)?;
+            if participants.insert(identifier, peer).is_some() {
+                return Err(SwarmError::ConfigurationError);
+            }
+        }
+        let members = data
+            .participants
+            .iter()
+            .flat_map(|old_participants| old_participants.values())
+            .chain(participants.values())
+            .copied()
+            .collect();
+        Ok(Self {
+            config: ReshareConfig {
+                dealers: BTreeMap::new(),
+                participants,
+                public_key_package: data
+                    .public_key_package
+                    .clone()
+                    .ok_or(SwarmError::DatabaseError)?,
+                signer_config,
+            },
+            dealing: false,
+            members,
+            min_dealers: old_config.min_signers as usize,
+            pings: HashMap::new(),
+            query_id,
+            responses: BTreeMap::new(),
+            response_channel,
+            started: Instant::now(),
+        })
+    }
+
+    // THE FIRST min_signers OLD PARTICIPANTS TO ANSWER A PING DEAL, SO AN OFFLINE ONE CANNOT
+    // STALL THE RESHARE
+    pub(crate) fn probe(
+        &mut self,
+        swarm: &mut Libp2pSwarm<Behaviour>,
+        old_participants: &BTreeMap<Identifier, PeerId>,
+    ) -> Result<(), SwarmError> {
+        for (identifier, peer) in old_participants {
+            match swarm
+                .behaviour_mut()
+                .req_res
+                .send_request(peer, DirectMsgData::Ping(self.query_id.to_string()))
+            {
+                Some(request_id) => {
+                    self.pings.insert(request_id, (*identifier, *peer));
+                }
+                // THE REQUESTER'S OWN SHARE IS ALWAYS AT HAND
+                None => self.insert_dealer(*identifier, *peer, swarm)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn answer_ping(
+        &mut self,
+        request_id: &OutboundRequestId,
+        swarm: &mut Libp2pSwarm<Behaviour>,
+    ) -> Result<(), SwarmError> {
+        let Some((identifier, peer)) = self.pings.remove(request_id) else {
+            return Ok(());
+        };
+        self.insert_dealer(identifier, peer, swarm)
+    }
+
+    fn insert_dealer(
+        &mut self,
+        identifier: Identifier,
+        peer: PeerId,
+        swarm: &mut Libp2pSwarm<Behaviour>,
+    ) -> Result<(), SwarmError> {
+        if self.dealing {
+            return Ok(());
+        }
+        self.config.dealers.insert(identifier, peer);
+        if self.config.dealers.len() >= self.min_dealers {
+            self.dealing = true;
+            self.start(swarm)?;
+        }
+        Ok(())
+    }
+
+    // TRUE ONCE TOO FEW OLD PARTICIPANTS ARE LEFT TO EVER REACH THE THRESHOLD
+    fn remove_candidate(&mut self, request_id: &OutboundRequestId) -> bool {
+        self.pings.remove(request_id).is_some()
+            && !self.dealing
+            && self.config.dealers.len() + self.pings.len() < self.min_dealers
+    }
+
+    pub(crate) fn start(&self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        for peer in &self.members {
+            let _ = swarm.behaviour_mut().req_res.send_request(
+                peer,
+                DirectMsgData::ReshareStart(self.query_id.to_string(), self.config.clone()),
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn insert_response(
+        &mut self,
+        peer: PeerId,
+        pubkey_package: PublicKeyPackage,
+    ) -> Result<usize, SwarmError> {
+        if !self
+            .config
+            .participants
+            .values()
+            .any(|participant| *participant == peer)
+        {
+            return Err(SwarmError::InvalidPeer);
+        }
+        self.responses.insert(peer, pubkey_package);
+        Ok(self.responses.len())
+    }
+
+    pub(crate) fn is_expired(&self, timeouts: &TimeoutConfig) -> bool {
+        self.started.elapsed() > timeouts.overall
+    }
+
+    pub(crate) fn is_member(&self, peer: &PeerId) -> bool {
+        self.members.contains(peer)
+    }
+
+    pub(crate) fn finish(&self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
+        for peer in &self.members {
+            let _ = swarm.behaviour_mut().req_res.send_request(
+                peer,
+                DirectMsgData::ReshareFinish(self.query_id.to_string()),
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.response_channel.is_canceled()
+    }
+
+    pub(crate) fn send_response(
+        self,
+        response: Result<PublicKeyPackage, SwarmError>,
+    ) -> Result<(), SwarmError> {
         let _ = self.response_channel.send(response);
         Ok(())
     }
@@ -163,52 +722,985 @@ pub(crate) fn handle_add_peer_input(
     multiaddress: Multiaddr,
     swarm: &mut Libp2pSwarm<Behaviour>,
 ) -> Result<(), SwarmError> {
-    let peer = peerid_from_multiaddress(&multiaddress).ok_or(SwarmError::MessageProcessingError)?;
+    let peer = peerid_from_multiaddress(&multiaddress).ok_or(SwarmError::InvalidMultiaddr)?;
     swarm.behaviour_mut().kad.add_address(&peer, multiaddress);
     let _ = swarm.behaviour_mut().kad.bootstrap();
     Ok(())
 }
 
+pub(crate) fn handle_dial_input(
+    multiaddress: Multiaddr,
+    response_channel: oneshot::Sender<Result<PeerId, SwarmError>>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    pending_dials: &DashMap<ConnectionId, oneshot::Sender<Result<PeerId, SwarmError>>>,
+) -> Result<(), SwarmError> {
+    let opts = DialOpts::from(multiaddress);
+    let connection_id = opts.connection_id();
+    if swarm.dial(opts).is_err() {
+        let _ = response_channel.send(Err(SwarmError::DialFailure));
+        return Ok(());
+    }
+    pending_dials.insert(connection_id, response_channel);
+    Ok(())
+}
+
+pub(crate) fn handle_subscribe_input(
+    topic: String,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+) -> Result<(), SwarmError> {
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&IdentTopic::new(format!("{APP_TOPIC_PREFIX}{topic}")))
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+    Ok(())
+}
+
+pub(crate) fn handle_unsubscribe_input(
+    topic: String,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+) -> Result<(), SwarmError> {
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .unsubscribe(&IdentTopic::new(format!("{APP_TOPIC_PREFIX}{topic}")))
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+    Ok(())
+}
+
+pub(crate) fn handle_publish_input(
+    topic: String,
+    data: Vec<u8>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+) -> Result<(), SwarmError> {
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(IdentTopic::new(format!("{APP_TOPIC_PREFIX}{topic}")), data)
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+    Ok(())
+}
+
+pub(crate) fn handle_generation_progress(
+    mut output: UnboundedSender<SwarmOutput>,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    message: &GenerationMessage,
+    query_id: &QueryId,
+) -> Result<(), SwarmError> {
+    let mut generation_requester = generation_requester_db
+        .get_mut(query_id)
+        .ok_or(SwarmError::DatabaseError)?;
+    if let Some((round, received)) = generation_requester.insert_progress(message)? {
+        let _ = output.start_send(SwarmOutput::GenerationProgress(
+            generation_requester.origin.clone(),
+            round,
+            received,
+            generation_requester.signer_config.max_signers as usize,
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_remove_peer_input(
+    peer: PeerId,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+) -> Result<(), SwarmError> {
+    let _ = swarm.behaviour_mut().kad.remove_peer(&peer);
+    let _ = swarm.disconnect_peer_id(peer);
+    Ok(())
+}
+
 pub(crate) fn handle_generate_input(
     query_id: QueryId,
     signer_config: SignerConfig,
-    response_channel: oneshot::Sender<VerifyingKey>,
+    response_channel: oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
     swarm: &mut Libp2pSwarm<Behaviour>,
     generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    topic_hashing: TopicHashing,
+    retries: u32,
 ) -> Result<(), SwarmError> {
+    let _span = info_span!("generate", query_id = %query_id).entered();
     let peer_list = get_peers_list(swarm);
     let mut generate_request = ReqGenerate::new(
         peer_list,
         query_id.clone(),
         response_channel,
         signer_config.clone(),
+        topic_hashing,
+        retries,
+    );
+    if !generate_request.has_enough_peers() {
+        warn!(
+            known_peers = generate_request.peers.len(),
+            max_signers = signer_config.max_signers,
+            "not enough peers to generate"
+        );
+        generate_request.send_response(Err(SwarmError::InsufficientPeers))?;
+        return Ok(());
+    }
+    info!(
+        min_signers = signer_config.min_signers,
+        max_signers = signer_config.max_signers,
+        "starting generation"
     );
     generate_request.gen_r1(swarm)?;
     generation_requester_db.insert(query_id.clone(), generate_request);
     Ok(())
 }
 
+pub(crate) fn handle_wait_for_peers_input(
+    count: usize,
+    response_channel: oneshot::Sender<()>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    peer_waiters: &Mutex<Vec<(usize, oneshot::Sender<()>)>>,
+) -> Result<(), SwarmError> {
+    if swarm.connected_peers().count() >= count {
+        let _ = response_channel.send(());
+        return Ok(());
+    }
+    peer_waiters
+        .lock()
+        .map_err(|_| SwarmError::DatabaseError)?
+        .push((count, response_channel));
+    Ok(())
+}
+
+pub(crate) fn notify_peer_waiters(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    peer_waiters: &Mutex<Vec<(usize, oneshot::Sender<()>)>>,
+) -> Result<(), SwarmError> {
+    let connected = swarm.connected_peers().count();
+    let mut peer_waiters = peer_waiters.lock().map_err(|_| SwarmError::DatabaseError)?;
+    let (ready, waiting) = peer_waiters
+        .drain(..)
+        .filter(|(_, response_channel)| !response_channel.is_canceled())
+        .partition::<Vec<_>, _>(|(count, _)| connected >= *count);
+    *peer_waiters = waiting;
+    for (_, response_channel) in ready {
+        let _ = response_channel.send(());
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_can_generate_input(
+    total_peers: u16,
+    response_channel: oneshot::Sender<Result<Vec<PeerId>, SwarmError>>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+) -> Result<(), SwarmError> {
+    let mut candidates = get_peers_list(swarm);
+    candidates.retain(|peer| swarm.is_connected(peer));
+    if candidates.len() + 1 < total_peers as usize {
+        let _ = response_channel.send(Err(SwarmError::InsufficientPeers));
+        return Ok(());
+    }
+    let _ = response_channel.send(Ok(candidates));
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn handle_sign_input(
     query_id: QueryId,
-    response_channel: oneshot::Sender<Signature>,
+    response_channel: oneshot::Sender<Result<Signature, SwarmError>>,
     public_key: Vec<u8>,
     message: Vec<u8>,
+    signers: Option<Vec<PeerId>>,
+    contextual: bool,
     swarm: &mut Libp2pSwarm<Behaviour>,
     signer_requester_db: &DashMap<QueryId, ReqSign>,
-    database: &DashMap<Vec<u8>, DbData>,
+    presigned_db: &DashMap<Vec<u8>, Vec<Presigned>>,
+    database: &Database,
 ) -> Result<(), SwarmError> {
-    let signer_config = database
-        .get(&public_key)
-        .and_then(|data| data.signer_config.clone())
-        .ok_or(SwarmError::DatabaseError)?;
-    let sign_requester = ReqSign::new(
+    let _span = info_span!("sign", query_id = %query_id).entered();
+    let Some((data, signer_config, public_key_package)) =
+        database.get(&public_key).and_then(|data| {
+            let signer_config = data.signer_config.clone()?;
+            let public_key_package = data.public_key_package.clone()?;
+            Some((data.clone(), signer_config, public_key_package))
+        })
+    else {
+        warn!("no group is known for the requested key");
+        let _ = response_channel.send(Err(SwarmError::UnknownKey));
+        return Ok(());
+    };
+    let signers = signers.map(BTreeSet::from_iter);
+    if let Some(signers) = &signers {
+        let known = data.participants.unwrap_or_default();
+        if signers.len() < signer_config.min_signers as usize
+            || !signers
+                .iter()
+                .all(|peer| known.values().any(|known_peer| known_peer == peer))
+        {
+            let _ = response_channel.send(Err(SwarmError::ConfigurationError));
+            return Ok(());
+        }
+    }
+    // EXPLICIT SIGNER SETS ALWAYS RUN THE COMMITMENT ROUND
+    let presigned = match &signers {
+        Some(_) => None,
+        None => take_presigned(presigned_db, &public_key),
+    };
+    let mut sign_requester = ReqSign::new(
         message,
         query_id.clone(),
-        public_key,
+        public_key.clone(),
+        public_key_package,
         response_channel,
         signer_config,
+        signers,
     );
-    sign_requester.sign_r1(swarm)?;
+    sign_requester.contextual = contextual;
+    let _ = swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&IdentTopic::new(b64.encode(public_key)));
+    match presigned {
+        Some(presigned) => {
+            info!(session_id = %presigned.session_id, "starting presigned signing");
+            sign_requester.sign_presigned(swarm, presigned)?;
+        }
+        None => {
+            info!("starting signing");
+            sign_requester.sign_r1(swarm)?;
+        }
+    }
     signer_requester_db.insert(query_id, sign_requester);
     Ok(())
 }
+
+pub(crate) fn handle_presign_input(
+    query_id: QueryId,
+    public_key: Vec<u8>,
+    count: u16,
+    response_channel: oneshot::Sender<Result<usize, SwarmError>>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    presign_requester_db: &DashMap<QueryId, ReqPresign>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let _span = info_span!("presign", query_id = %query_id).entered();
+    if count == 0 || count > MAX_PRESIGN_BATCH {
+        let _ = response_channel.send(Err(SwarmError::ConfigurationError));
+        return Ok(());
+    }
+    let Some(signer_config) = database
+        .get(&public_key)
+        .and_then(|data| data.signer_config.clone())
+    else {
+        warn!("no group is known for the requested key");
+        let _ = response_channel.send(Err(SwarmError::UnknownKey));
+        return Ok(());
+    };
+    let presign_requester = ReqPresign::new(
+        count,
+        public_key.clone(),
+        query_id.clone(),
+        response_channel,
+        signer_config,
+    );
+    let _ = swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&IdentTopic::new(b64.encode(public_key)));
+    info!(count, "starting presigning");
+    presign_requester.request(swarm)?;
+    presign_requester_db.insert(query_id, presign_requester);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_reshare_input(
+    query_id: QueryId,
+    public_key: Vec<u8>,
+    signer_config: SignerConfig,
+    new_peers: Vec<Multiaddr>,
+    response_channel: oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let Some(data) = database.get(&public_key).map(|data| data.clone()) else {
+        let _ = response_channel.send(Err(SwarmError::UnknownKey));
+        return Ok(());
+    };
+    let participants = data.participants.clone().unwrap_or_default();
+    let local_peer_id = *swarm.local_peer_id();
+    let mut peers = BTreeSet::new();
+    for multiaddress in &new_peers {
+        match peerid_from_multiaddress(multiaddress) {
+            Some(peer)
+                if !participants
+                    .values()
+                    .any(|participant| *participant == peer) =>
+            {
+                peers.insert(peer);
+            }
+            Some(_) => {}
+            None => {
+                let _ = response_channel.send(Err(SwarmError::ConfigurationError));
+                return Ok(());
+            }
+        }
+    }
+    if signer_config.min_signers < 2
+        || signer_config.min_signers > signer_config.max_signers
+        || signer_config.max_signers as usize != participants.len() + peers.len()
+        || peers.contains(&local_peer_id)
+    {
+        let _ = response_channel.send(Err(SwarmError::ConfigurationError));
+        return Ok(());
+    }
+    for multiaddress in new_peers {
+        handle_add_peer_input(multiaddress, swarm)?;
+    }
+    let mut reshare_request = ReqReshare::new(
+        &data,
+        query_id.clone(),
+        response_channel,
+        signer_config,
+        peers.into_iter().collect(),
+    )?;
+    reshare_request.probe(swarm, &participants)?;
+    reshare_requester_db.insert(query_id, reshare_request);
+    Ok(())
+}
+
+pub(crate) fn handle_listeners_input(
+    response_channel: oneshot::Sender<Vec<Multiaddr>>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+) -> Result<(), SwarmError> {
+    let listeners = swarm.listeners().cloned().collect::<Vec<_>>();
+    let _ = response_channel.send(listeners);
+    Ok(())
+}
+
+pub(crate) fn handle_key_package_input(
+    public_key: Vec<u8>,
+    response_channel: oneshot::Sender<Option<KeyPackage>>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let key_package = database
+        .get(&public_key)
+        .and_then(|data| data.key_package.clone());
+    let _ = response_channel.send(key_package);
+    Ok(())
+}
+
+pub(crate) fn handle_signer_config_input(
+    public_key: Vec<u8>,
+    response_channel: oneshot::Sender<Option<SignerConfig>>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let signer_config = database
+        .get(&public_key)
+        .and_then(|data| data.signer_config.clone());
+    let _ = response_channel.send(signer_config);
+    Ok(())
+}
+
+pub(crate) fn handle_list_keys_input(
+    response_channel: oneshot::Sender<Vec<VerifyingKey>>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let keys = database
+        .keys()
+        .into_iter()
+        .filter(|key| {
+            database
+                .get(key)
+                .is_some_and(|data| data.key_package.is_some())
+        })
+        .filter_map(|key| VerifyingKey::deserialize(key.try_into().ok()?).ok())
+        .collect();
+    let _ = response_channel.send(keys);
+    Ok(())
+}
+
+pub(crate) fn handle_participant_map_input(
+    public_key: Vec<u8>,
+    response_channel: oneshot::Sender<Vec<(PeerId, Identifier)>>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let participant_map = database
+        .get(&public_key)
+        .and_then(|data| data.participants.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(identifier, peer)| (peer, identifier))
+        .collect();
+    let _ = response_channel.send(participant_map);
+    Ok(())
+}
+
+pub(crate) fn handle_export_group_input(
+    public_key: Vec<u8>,
+    response_channel: oneshot::Sender<Option<GroupBundle>>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let bundle = database.get(&public_key).and_then(|data| {
+        Some(GroupBundle {
+            key_package: data.key_package.clone()?,
+            public_key_package: data.public_key_package.clone()?,
+            signer_config: data.signer_config.clone()?,
+            participants: data.participants.clone()?,
+        })
+    });
+    let _ = response_channel.send(bundle);
+    Ok(())
+}
+
+pub(crate) fn handle_import_group_input(
+    bundle: GroupBundle,
+    response_channel: oneshot::Sender<Result<(), SwarmError>>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let verifying_key = bundle.public_key_package.verifying_key();
+    if bundle.key_package.verifying_key() != verifying_key
+        || !bundle.signer_config.is_valid()
+        || !bundle
+            .participants
+            .contains_key(bundle.key_package.identifier())
+    {
+        let _ = response_channel.send(Err(SwarmError::ConfigurationError));
+        return Ok(());
+    }
+    let public_key = verifying_key.serialize().to_vec();
+    let topic = IdentTopic::new(b64.encode(&public_key));
+    let inserted = database.insert(
+        public_key,
+        DbData {
+            identifier: Some(*bundle.key_package.identifier()),
+            key_package: Some(bundle.key_package),
+            participants: Some(bundle.participants),
+            public_key_package: Some(bundle.public_key_package),
+            signer_config: Some(bundle.signer_config),
+        },
+    );
+    if inserted.is_ok() {
+        let _ = swarm.behaviour_mut().gossipsub.subscribe(&topic);
+    }
+    let _ = response_channel.send(inserted);
+    Ok(())
+}
+
+pub(crate) fn handle_public_key_package_input(
+    public_key: Vec<u8>,
+    response_channel: oneshot::Sender<Option<PublicKeyPackage>>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let public_key_package = database
+        .get(&public_key)
+        .and_then(|data| data.public_key_package.clone());
+    let _ = response_channel.send(public_key_package);
+    Ok(())
+}
+
+pub(crate) fn handle_group_status_input(
+    public_key: Vec<u8>,
+    response_channel: oneshot::Sender<Option<GroupStatus>>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let group_status = database.get(&public_key).and_then(|data| {
+        let participants = data.participants.as_ref()?;
+        let signer_config = data.signer_config.as_ref()?;
+        let online = participants
+            .values()
+            .filter(|peer| swarm.is_connected(peer))
+            .count();
+        Some(GroupStatus {
+            total: participants.len(),
+            online,
+            threshold: signer_config.min_signers,
+        })
+    });
+    let _ = response_channel.send(group_status);
+    Ok(())
+}
+
+pub(crate) fn emit_topology(
+    mut output: UnboundedSender<SwarmOutput>,
+    interval: Duration,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    last_topology: &Mutex<Instant>,
+) -> Result<(), SwarmError> {
+    let mut last_topology = last_topology
+        .lock()
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+    if last_topology.elapsed() < interval {
+        return Ok(());
+    }
+    *last_topology = Instant::now();
+    let mut known_addresses = HashMap::new();
+    let mut buckets = Vec::new();
+    for bucket in swarm.behaviour_mut().kad.kbuckets() {
+        buckets.push((
+            bucket.range().0.ilog2().unwrap_or_default(),
+            bucket.num_entries(),
+        ));
+        for entry in bucket.iter() {
+            known_addresses.insert(
+                *entry.node.key.preimage(),
+                entry.node.value.iter().cloned().collect::<Vec<_>>(),
+            );
+        }
+    }
+    let connected_peers = swarm
+        .connected_peers()
+        .map(|peer| (*peer, known_addresses.remove(peer).unwrap_or_default()))
+        .collect();
+    let _ = output.start_send(SwarmOutput::TopologySnapshot(TopologySnapshot {
+        connected_peers,
+        buckets,
+    }));
+    Ok(())
+}
+
+pub(crate) fn handle_health_input(
+    pending_queries: usize,
+    accepting: bool,
+    response_channel: oneshot::Sender<HealthSnapshot>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    database: &Database,
+) -> Result<(), SwarmError> {
+    let held_keys = database
+        .keys()
+        .into_iter()
+        .filter(|key| {
+            database
+                .get(key)
+                .is_some_and(|data| data.key_package.is_some())
+        })
+        .count();
+    let _ = response_channel.send(HealthSnapshot {
+        alive: true,
+        accepting,
+        connected_peers: swarm.connected_peers().count(),
+        pending_queries,
+        held_keys,
+        subscribed_topics: swarm.behaviour().gossipsub.topics().count(),
+        listeners: swarm.listeners().cloned().collect(),
+    });
+    Ok(())
+}
+
+pub(crate) fn handle_cancel_input(
+    query_id: QueryId,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+    presign_requester_db: &DashMap<QueryId, ReqPresign>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+) -> Result<(), SwarmError> {
+    let generation = generation_session(generation_requester_db, &query_id);
+    if let Some((_, generate_request)) =
+        generation.and_then(|session| generation_requester_db.remove(&session))
+    {
+        generate_request.abort(swarm)?;
+        generate_request.send_response(Err(SwarmError::Cancelled))?;
+    } else if let Some((_, sign_request)) = signer_requester_db.remove(&query_id) {
+        sign_request.abort(swarm)?;
+        sign_request.send_response(Err(SwarmError::Cancelled))?;
+    } else if let Some((_, presign_request)) = presign_requester_db.remove(&query_id) {
+        presign_request.send_response(Err(SwarmError::Cancelled))?;
+    } else if let Some((_, reshare_request)) = reshare_requester_db.remove(&query_id) {
+        reshare_request.send_response(Err(SwarmError::Cancelled))?;
+    } else {
+        // THE REQUEST MAY HAVE FINISHED WHILE THE CANCEL WAS QUEUED
+        debug!(query_id = %query_id, "nothing to cancel");
+    }
+    Ok(())
+}
+
+/// The session a caller's id currently runs under, which is a different id once a generation
+/// has been retried.
+pub(crate) fn generation_session(
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    query_id: &QueryId,
+) -> Option<QueryId> {
+    if generation_requester_db.contains_key(query_id) {
+        return Some(query_id.clone());
+    }
+    generation_requester_db
+        .iter()
+        .find(|entry| entry.origin == *query_id)
+        .map(|entry| entry.key().clone())
+}
+
+/// Ends a generation with `error`, unless the error is recoverable and retries are left, in
+/// which case the generation restarts with fresh peers under a new session id.
+pub(crate) fn fail_generation(
+    generate_request: ReqGenerate,
+    error: SwarmError,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+) -> Result<(), SwarmError> {
+    generate_request.abort(swarm)?;
+    if !error.is_recoverable() || generate_request.attempt >= generate_request.retries {
+        return generate_request.send_response(Err(error));
+    }
+    // A FRESH SESSION ID KEEPS PARTICIPANTS OF THE FAILED RUN OUT OF THE RETRY
+    let mut query_id = new_query_id();
+    while generation_session(generation_requester_db, &query_id).is_some() {
+        query_id = new_query_id();
+    }
+    let peer_list = get_peers_list(swarm);
+    let mut retry = generate_request.into_retry(peer_list, query_id);
+    info!(
+        query_id = %retry.origin,
+        session = %retry.query_id,
+        attempt = retry.attempt,
+        reason = %error,
+        "retrying generation"
+    );
+    if !retry.has_enough_peers() {
+        return retry.send_response(Err(error));
+    }
+    retry.gen_r1(swarm)?;
+    generation_requester_db.insert(retry.query_id.clone(), retry);
+    Ok(())
+}
+
+pub(crate) fn abandon_canceled_requests(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+    presign_requester_db: &DashMap<QueryId, ReqPresign>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+) -> Result<(), SwarmError> {
+    let abandoned = generation_requester_db
+        .iter()
+        .filter(|entry| entry.is_canceled())
+        .map(|entry| entry.key().clone())
+        .chain(
+            signer_requester_db
+                .iter()
+                .filter(|entry| entry.is_canceled())
+                .map(|entry| entry.key().clone()),
+        )
+        .chain(
+            presign_requester_db
+                .iter()
+                .filter(|entry| entry.is_canceled())
+                .map(|entry| entry.key().clone()),
+        )
+        .chain(
+            reshare_requester_db
+                .iter()
+                .filter(|entry| entry.is_canceled())
+                .map(|entry| entry.key().clone()),
+        )
+        .collect::<Vec<_>>();
+    for query_id in abandoned {
+        debug!(query_id = %query_id, "caller dropped the request, abandoning session");
+        handle_cancel_input(
+            query_id,
+            swarm,
+            generation_requester_db,
+            signer_requester_db,
+            presign_requester_db,
+            reshare_requester_db,
+        )?;
+    }
+    Ok(())
+}
+
+// A REQUESTER WITHOUT A SHARE ONLY FOLLOWS A GROUP TOPIC WHILE IT HAS REQUESTS FOR THAT GROUP
+pub(crate) fn release_request_topics(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+    presign_requester_db: &DashMap<QueryId, ReqPresign>,
+    database: &Database,
+) {
+    let released = swarm
+        .behaviour()
+        .gossipsub
+        .topics()
+        .filter(|topic| {
+            let Ok(public_key) = b64.decode(topic.as_str()) else {
+                return false;
+            };
+            let requester_only = database
+                .get(&public_key)
+                .is_some_and(|data| data.key_package.is_none());
+            requester_only
+                && !signer_requester_db
+                    .iter()
+                    .any(|entry| entry.pubkey == public_key)
+                && !presign_requester_db
+                    .iter()
+                    .any(|entry| entry.pubkey == public_key)
+        })
+        .map(|topic| IdentTopic::new(topic.as_str()))
+        .collect::<Vec<_>>();
+    for topic in released {
+        debug!(topic = %topic, "no requests left for group, unsubscribing");
+        let _ = swarm.behaviour_mut().gossipsub.unsubscribe(&topic);
+    }
+}
+
+pub(crate) fn handle_shutdown_input(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    listeners: &[ListenerId],
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+    presign_requester_db: &DashMap<QueryId, ReqPresign>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+) -> Result<(), SwarmError> {
+    let in_flight = generation_requester_db
+        .iter()
+        .map(|entry| entry.key().clone())
+        .chain(signer_requester_db.iter().map(|entry| entry.key().clone()))
+        .chain(presign_requester_db.iter().map(|entry| entry.key().clone()))
+        .chain(reshare_requester_db.iter().map(|entry| entry.key().clone()))
+        .collect::<Vec<_>>();
+    for query_id in in_flight {
+        handle_cancel_input(
+            query_id,
+            swarm,
+            generation_requester_db,
+            signer_requester_db,
+            presign_requester_db,
+            reshare_requester_db,
+        )?;
+    }
+    for listener in listeners {
+        swarm.remove_listener(*listener);
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_unreachable_peer(
+    peer: PeerId,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+) -> Result<(), SwarmError> {
+    let affected_reshares = reshare_requester_db
+        .iter()
+        .filter(|entry| entry.is_member(&peer))
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for query_id in affected_reshares {
+        if let Some((_, reshare_request)) = reshare_requester_db.remove(&query_id) {
+            reshare_request.send_response(Err(SwarmError::ConfigurationError))?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_dropped_peer(
+    peer: PeerId,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+) -> Result<(), SwarmError> {
+    let affected_generations = generation_requester_db
+        .iter()
+        .filter(|entry| entry.is_selected(&peer))
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for query_id in affected_generations {
+        drop_generation_participant(peer, query_id, swarm, generation_requester_db)?;
+    }
+    Ok(())
+}
+
+fn drop_generation_participant(
+    peer: PeerId,
+    query_id: QueryId,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+) -> Result<(), SwarmError> {
+    // SUBSTITUTE DURING DISCOVERY, FAIL FAST ONCE ROUND 1 HAS STARTED
+    let replaced = match generation_requester_db.get_mut(&query_id) {
+        Some(mut generate_request) if !generate_request.discovered => {
+            generate_request.replace_peer(peer, swarm)?
+        }
+        _ => false,
+    };
+    if replaced {
+        info!(query_id = %query_id, %peer, "substituted dropped participant");
+        return Ok(());
+    }
+    if let Some((_, generate_request)) = generation_requester_db.remove(&query_id) {
+        warn!(query_id = %query_id, %peer, "participant dropped during generation");
+        fail_generation(
+            generate_request,
+            SwarmError::ParticipantDropped,
+            swarm,
+            generation_requester_db,
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_declined_invitation(
+    mut output: UnboundedSender<SwarmOutput>,
+    peer: PeerId,
+    query_id: QueryId,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+) -> Result<(), SwarmError> {
+    let selected = generation_requester_db
+        .get(&query_id)
+        .is_some_and(|generate_request| generate_request.is_selected(&peer));
+    if selected {
+        return drop_generation_participant(peer, query_id, swarm, generation_requester_db);
+    }
+    // BROADCAST SIGNING NEEDS NO ACTION, ANY OTHER SIGNER CAN ANSWER
+    let threshold_lost = match signer_requester_db.get_mut(&query_id) {
+        Some(mut sign_request) => {
+            let min_signers = sign_request.signer_config.min_signers() as usize;
+            match sign_request.signers.as_mut() {
+                Some(signers) => signers.remove(&peer) && signers.len() < min_signers,
+                None => false,
+            }
+        }
+        None => false,
+    };
+    if !threshold_lost {
+        return Ok(());
+    }
+    if let Some((_, sign_request)) = signer_requester_db.remove(&query_id) {
+        warn!(query_id = %query_id, %peer, "signer declined and threshold is unreachable");
+        let _ = output.start_send(SwarmOutput::SigningFailed(
+            query_id,
+            SwarmError::ThresholdNotMet(Some(peer)),
+        ));
+        sign_request.abort(swarm)?;
+        sign_request.send_response(Err(SwarmError::ThresholdNotMet(Some(peer))))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn ping_signers(
+    interval: Duration,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+) {
+    for mut sign_request in signer_requester_db.iter_mut() {
+        sign_request.ping(interval, swarm);
+    }
+}
+
+pub(crate) fn handle_ping_response(
+    request_id: OutboundRequestId,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+) -> Result<(), SwarmError> {
+    for mut sign_request in signer_requester_db.iter_mut() {
+        sign_request.pings.remove(&request_id);
+    }
+    for mut reshare_request in reshare_requester_db.iter_mut() {
+        reshare_request.answer_ping(&request_id, swarm)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_ping_failure(
+    mut output: UnboundedSender<SwarmOutput>,
+    request_id: OutboundRequestId,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+) -> Result<(), SwarmError> {
+    let stalled_reshares = reshare_requester_db
+        .iter_mut()
+        .filter_map(|mut entry| {
+            entry
+                .remove_candidate(&request_id)
+                .then(|| entry.key().clone())
+        })
+        .collect::<Vec<_>>();
+    for query_id in stalled_reshares {
+        if let Some((_, reshare_request)) = reshare_requester_db.remove(&query_id) {
+            warn!(query_id = %query_id, "too few old participants answered to deal");
+            reshare_request.send_response(Err(SwarmError::ThresholdNotMet(None)))?;
+        }
+    }
+    let Some((query_id, peer)) = signer_requester_db.iter().find_map(|entry| {
+        let peer = entry.pings.get(&request_id)?;
+        Some((entry.key().clone(), *peer))
+    }) else {
+        return Ok(());
+    };
+    if let Some((_, sign_request)) = signer_requester_db.remove(&query_id) {
+        warn!(query_id = %query_id, %peer, "signer stopped responding");
+        let _ = output.start_send(SwarmOutput::SigningFailed(
+            query_id,
+            SwarmError::ThresholdNotMet(Some(peer)),
+        ));
+        sign_request.abort(swarm)?;
+        sign_request.send_response(Err(SwarmError::ThresholdNotMet(Some(peer))))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn expire_requests(
+    mut output: UnboundedSender<SwarmOutput>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    timeouts: &TimeoutConfig,
+    generation_requester_db: &DashMap<QueryId, ReqGenerate>,
+    signer_requester_db: &DashMap<QueryId, ReqSign>,
+    presign_requester_db: &DashMap<QueryId, ReqPresign>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+) -> Result<(), SwarmError> {
+    let expired_generations = generation_requester_db
+        .iter()
+        .filter(|entry| entry.is_expired(timeouts))
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for query_id in expired_generations {
+        if let Some((_, generate_request)) = generation_requester_db.remove(&query_id) {
+            warn!(query_id = %query_id, "generation timed out");
+            fail_generation(
+                generate_request,
+                SwarmError::Timeout,
+                swarm,
+                generation_requester_db,
+            )?;
+        }
+    }
+    let expired_signings = signer_requester_db
+        .iter()
+        .filter(|entry| entry.is_expired(timeouts))
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for query_id in expired_signings {
+        if let Some((_, sign_request)) = signer_requester_db.remove(&query_id) {
+            let finished = sign_request.finished;
+            let reason = || {
+                if finished {
+                    SwarmError::Timeout
+                } else {
+                    SwarmError::ThresholdNotMet(None)
+                }
+            };
+            warn!(query_id = %query_id, reason = %reason(), "signing expired");
+            let _ = output.start_send(SwarmOutput::SigningFailed(query_id, reason()));
+            sign_request.abort(swarm)?;
+            sign_request.send_response(Err(reason()))?;
+        }
+    }
+    let expired_presigns = presign_requester_db
+        .iter()
+        .filter(|entry| entry.is_expired(timeouts))
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for query_id in expired_presigns {
+        if let Some((_, presign_request)) = presign_requester_db.remove(&query_id) {
+            warn!(query_id = %query_id, "presigning expired");
+            presign_request.send_response(Err(SwarmError::ThresholdNotMet(None)))?;
+        }
+    }
+    let expired_reshares = reshare_requester_db
+        .iter()
+        .filter(|entry| entry.is_expired(timeouts))
+        .map(|entry| entry.key().clone())
+        .collect::<Vec<_>>();
+    for query_id in expired_reshares {
+        if let Some((_, reshare_request)) = reshare_requester_db.remove(&query_id) {
+            reshare_request.send_response(Err(SwarmError::Timeout))?;
+        }
+    }
+    Ok(())
+}
@@ -1,128 +1,494 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine as Base64Engine};
 use dashmap::DashMap;
-use frost_ed25519::{
+use frost_ed25519::{keys::SecretShare, round1::SigningCommitments};
+pub use frost_ed25519::{
     keys::{KeyPackage, PublicKeyPackage},
-    round1::SigningCommitments,
-    Identifier,
+    Identifier, Signature, VerifyingKey,
 };
-pub use frost_ed25519::{Signature, VerifyingKey};
 use futures::{
-    channel::mpsc::{self, UnboundedSender},
+    channel::{
+        mpsc::{self, UnboundedSender},
+        oneshot,
+    },
     select, FutureExt, StreamExt,
 };
+use futures_timer::Delay;
 use libp2p::{
-    gossipsub::{self, Event as GossipsubEvent},
-    identify,
+    allow_block_list, autonat,
+    core::transport::ListenerId,
+    gossipsub::{self, Event as GossipsubEvent, IdentTopic, TopicHash},
+    identify, kad,
     request_response::{self, Message as ReqResMessage, ResponseChannel},
-    swarm::SwarmEvent,
+    swarm::{ConnectionId, DialError, ListenError, SwarmEvent},
     Swarm as Libp2pSwarm,
 };
 pub use libp2p::{
-    identity::Keypair, multiaddr::Protocol as MultiaddrProtocol, swarm::Executor, Multiaddr,
+    autonat::NatStatus,
+    gossipsub::{
+        Config as GossipsubConfig, ConfigBuilder as GossipsubConfigBuilder, MessageAcceptance,
+    },
+    identity::Keypair,
+    multiaddr::Protocol as MultiaddrProtocol,
+    swarm::Executor,
+    Multiaddr, PeerId,
 };
 use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
+#[cfg(feature = "metrics")]
+pub use crate::bandwidth::PeerBandwidth;
 pub use crate::builder::Builder;
+pub use crate::database::StorageBackend;
+#[cfg(feature = "metrics")]
+pub use crate::metrics::{LatencyHistogram, MetricsSnapshot};
 use crate::swarm::SwarmError;
+pub use crate::swarm::{
+    Authenticity, ConnectionLimitConfig, EventFilter, GroupBundle, GroupStatus, HealthSnapshot,
+    MessageValidator, MultiSignMode, PeerScoring, RateLimit, RequestResponseConfig, Security,
+    SigningMode, TimeoutConfig, TopicHashing, TopologySnapshot, TransportKind,
+};
+#[cfg(feature = "test-util")]
+pub use crate::test_util::TestCluster;
+pub use crate::utils::{
+    deserialize_signature, deserialize_verifying_key, group_public_key, keypair_from_seed,
+    load_keypair, save_keypair, serialize_signature, serialize_verifying_key, verify, verify_share,
+    verify_with_context, ENCODING_VERSION,
+};
 use crate::{
+    database::Database,
+    direct::Loopback,
     gen::{gen_start, send_final_gen, GenerationMessage, Generator},
-    input::{ReqGenerate, ReqSign},
-    sign::{send_signature, signing_package, Signer, SigningMessage},
-    swarm::{Behaviour, BehaviourEvent, SwarmInput, SwarmOutput},
+    input::{ReqGenerate, ReqPresign, ReqReshare, ReqSign},
+    limiter::RateLimiter,
+    presign::{
+        handle_presign_request, handle_presigned_sign, presign_commitments, PresignMessage,
+        Presigned, Presigner,
+    },
+    reshare::{
+        handle_reshare_finish, handle_reshare_share, reshare_start, send_final_reshare,
+        ReshareConfig, Resharer,
+    },
+    scoring::PeerScorer,
+    sign::{send_signature, sign_request, signing_package, Signer, SigningMessage},
+    swarm::{Behaviour, BehaviourEvent, Round, Settings, SwarmInput, SwarmOutput},
 };
 
+#[cfg(feature = "metrics")]
+mod bandwidth;
 pub mod builder;
+pub mod database;
+mod direct;
 pub mod gen;
 pub mod input;
+mod limiter;
+#[cfg(feature = "metrics")]
+mod metrics;
+pub mod presign;
+pub mod reshare;
+mod scoring;
 pub mod sign;
 pub mod swarm;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 pub mod utils;
 
+type Ciphersuite = frost_ed25519::Ed25519Sha512;
+
 type QueryId = String;
 
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+const APP_TOPIC_PREFIX: &str = "app:";
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SignerConfig {
     max_signers: u16,
     min_signers: u16,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl SignerConfig {
+    pub fn new(min_signers: u16, max_signers: u16) -> Self {
+        Self {
+            max_signers,
+            min_signers,
+        }
+    }
+
+    pub fn min_signers(&self) -> u16 {
+        self.min_signers
+    }
+
+    pub fn max_signers(&self) -> u16 {
+        self.max_signers
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        // FROST NEEDS AT LEAST TWO SIGNERS ON BOTH ENDS
+        self.min_signers >= 2 && self.max_signers >= 2 && self.min_signers <= self.max_signers
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum DirectMsgData {
-    GenStart(QueryId, SignerConfig, u16),
+    Decline(QueryId),
+    GenStart(QueryId, SignerConfig),
+    Ping(QueryId),
+    PresignCommitments(QueryId, Identifier, Vec<SigningCommitments>),
+    ReshareFinish(QueryId),
+    ReshareShare(QueryId, Identifier, SecretShare),
+    ReshareStart(QueryId, ReshareConfig),
     ReturnGen(QueryId, PublicKeyPackage),
+    ReturnReshare(QueryId, PublicKeyPackage),
     ReturnSign(QueryId, Signature),
+    SignRequest(QueryId, Vec<u8>),
     SigningPackage(QueryId, Identifier, SigningCommitments),
 }
 
 #[derive(Deserialize, Serialize)]
 enum MessageData {
     Generation(GenerationMessage),
+    Presigning(PresignMessage),
     Signing(SigningMessage),
 }
 
-#[derive(Clone)]
+// DROP UNDECODABLE PAYLOADS INSTEAD OF LETTING THEM REACH THE HANDLERS
+fn decode_message(source: PeerId, data: &[u8]) -> Result<MessageData, SwarmError> {
+    bincode::deserialize::<MessageData>(data).map_err(|_| SwarmError::MalformedMessage(source))
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct DbData {
     identifier: Option<Identifier>,
     key_package: Option<KeyPackage>,
+    participants: Option<BTreeMap<Identifier, PeerId>>,
     public_key_package: Option<PublicKeyPackage>,
     signer_config: Option<SignerConfig>,
 }
 
 async fn start_swarm(
-    mut input: mpsc::UnboundedReceiver<SwarmInput>,
+    mut input: mpsc::Receiver<SwarmInput>,
     output: UnboundedSender<SwarmOutput>,
     mut swarm: Libp2pSwarm<Behaviour>,
+    listeners: Vec<ListenerId>,
+    database: Database,
+    settings: Settings,
 ) -> Result<(), SwarmError> {
     let generation_requester_db = Arc::new(DashMap::<QueryId, ReqGenerate>::new());
     let generator_db = Arc::new(DashMap::<QueryId, Generator>::new());
     let signer_db = Arc::new(DashMap::<QueryId, Signer>::new());
     let signer_requester_db = Arc::new(DashMap::<QueryId, ReqSign>::new());
-    let database = Arc::new(DashMap::<Vec<u8>, DbData>::new());
+    let presigner_db = DashMap::<QueryId, Presigner>::new();
+    let presign_requester_db = DashMap::<QueryId, ReqPresign>::new();
+    let presigned_db = DashMap::<Vec<u8>, Vec<Presigned>>::new();
+    let resharer_db = Arc::new(DashMap::<QueryId, Resharer>::new());
+    let reshare_requester_db = Arc::new(DashMap::<QueryId, ReqReshare>::new());
+    let database = Arc::new(database);
+    let rate_limiter = RateLimiter::new(settings.rate_limit.clone());
+    let peer_scorer = PeerScorer::new(settings.peer_scoring.clone());
+    let pending_dials = DashMap::<ConnectionId, oneshot::Sender<Result<PeerId, SwarmError>>>::new();
+    let accepting = AtomicBool::new(true);
+    let peer_waiters = Mutex::new(Vec::<(usize, oneshot::Sender<()>)>::new());
+    let last_topology = Mutex::new(Instant::now());
+    let shutdown_request = Mutex::new(None::<oneshot::Sender<()>>);
+
+    // RESUBSCRIBE TO GROUPS LOADED FROM STORAGE
+    for key in database.keys() {
+        let holds_share = database
+            .get(&key)
+            .is_some_and(|data| data.key_package.is_some());
+        if !holds_share {
+            continue;
+        }
+        let _ = swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&IdentTopic::new(b64.encode(key)));
+    }
 
     // HANDLE INPUT FROM CLIENT
+    let query_in_flight = |query_id: &QueryId| -> bool {
+        input::generation_session(&generation_requester_db, query_id).is_some()
+            || signer_requester_db.contains_key(query_id)
+            || presign_requester_db.contains_key(query_id)
+            || reshare_requester_db.contains_key(query_id)
+    };
+    let active_sessions = || -> usize {
+        generation_requester_db.len() + signer_requester_db.len() + reshare_requester_db.len()
+    };
+    let at_capacity = || -> bool {
+        settings
+            .max_concurrent_sessions
+            .is_some_and(|max_sessions| active_sessions() >= max_sessions)
+    };
+    // PROTOCOL SESSIONS NEED AUTHORED MESSAGES
+    let anonymous = matches!(settings.authenticity, Authenticity::Anonymous);
     let handle_client_input = |input: SwarmInput,
                                swarm: &mut Libp2pSwarm<Behaviour>|
      -> Result<(), SwarmError> {
         match input {
             SwarmInput::AddPeer(peer_address) => input::handle_add_peer_input(peer_address, swarm)?,
+            SwarmInput::RemovePeer(peer) => input::handle_remove_peer_input(peer, swarm)?,
+            SwarmInput::Dial(peer_address, resp_channel) => {
+                input::handle_dial_input(peer_address, resp_channel, swarm, &pending_dials)?
+            }
+            SwarmInput::Subscribe(topic) => input::handle_subscribe_input(topic, swarm)?,
+            SwarmInput::Unsubscribe(topic) => input::handle_unsubscribe_input(topic, swarm)?,
+            SwarmInput::Publish(topic, data) => input::handle_publish_input(topic, data, swarm)?,
+            SwarmInput::Generate(_, _, resp_channel) if anonymous => {
+                let _ = resp_channel.send(Err(SwarmError::ConfigurationError));
+            }
+            SwarmInput::Sign(_, resp_channel, ..) if anonymous => {
+                let _ = resp_channel.send(Err(SwarmError::ConfigurationError));
+            }
+            SwarmInput::Presign(_, _, _, resp_channel) if anonymous => {
+                let _ = resp_channel.send(Err(SwarmError::ConfigurationError));
+            }
+            SwarmInput::Reshare(.., resp_channel) if anonymous => {
+                let _ = resp_channel.send(Err(SwarmError::ConfigurationError));
+            }
+            SwarmInput::Generate(req_id, _, resp_channel) if query_in_flight(&req_id) => {
+                let _ = resp_channel.send(Err(SwarmError::ConfigurationError));
+            }
+            SwarmInput::Sign(req_id, resp_channel, ..) if query_in_flight(&req_id) => {
+                let _ = resp_channel.send(Err(SwarmError::ConfigurationError));
+            }
+            SwarmInput::Generate(_, _, resp_channel) if at_capacity() => {
+                let _ = resp_channel.send(Err(SwarmError::TooManySessions));
+            }
+            SwarmInput::Sign(_, resp_channel, ..) if at_capacity() => {
+                let _ = resp_channel.send(Err(SwarmError::TooManySessions));
+            }
+            SwarmInput::CanGenerate(total_peers, resp_channel) => {
+                input::handle_can_generate_input(total_peers, resp_channel, swarm)?
+            }
+            SwarmInput::WaitForPeers(count, resp_channel) => {
+                input::handle_wait_for_peers_input(count, resp_channel, swarm, &peer_waiters)?
+            }
             SwarmInput::Generate(req_id, signer_conf, resp_channel) => {
                 input::handle_generate_input(
-                    req_id,
+                    req_id.clone(),
                     signer_conf,
                     resp_channel,
                     swarm,
                     &generation_requester_db,
+                    settings.topic_hashing,
+                    settings.generate_retries,
+                )?;
+                #[cfg(feature = "metrics")]
+                if generation_requester_db.contains_key(&req_id) {
+                    metrics::increment(&settings.metrics.generations_started);
+                }
+            }
+            SwarmInput::Sign(req_id, resp_channel, public_key, msg, signers, contextual) => {
+                input::handle_sign_input(
+                    req_id,
+                    resp_channel,
+                    public_key,
+                    msg,
+                    signers,
+                    contextual,
+                    swarm,
+                    &signer_requester_db,
+                    &presigned_db,
+                    &database,
                 )?
             }
-            SwarmInput::Sign(req_id, resp_channel, public_key, msg) => input::handle_sign_input(
-                req_id,
-                resp_channel,
-                public_key,
-                msg,
+            SwarmInput::Presign(req_id, _, _, resp_channel) if query_in_flight(&req_id) => {
+                let _ = resp_channel.send(Err(SwarmError::ConfigurationError));
+            }
+            SwarmInput::Presign(req_id, public_key, count, resp_channel) => {
+                input::handle_presign_input(
+                    req_id,
+                    public_key,
+                    count,
+                    resp_channel,
+                    swarm,
+                    &presign_requester_db,
+                    &database,
+                )?
+            }
+            SwarmInput::Reshare(req_id, public_key, signer_conf, new_peers, resp_channel) => {
+                input::handle_reshare_input(
+                    req_id,
+                    public_key,
+                    signer_conf,
+                    new_peers,
+                    resp_channel,
+                    swarm,
+                    &reshare_requester_db,
+                    &database,
+                )?
+            }
+            SwarmInput::Cancel(query_id) => input::handle_cancel_input(
+                query_id,
                 swarm,
+                &generation_requester_db,
                 &signer_requester_db,
+                &presign_requester_db,
+                &reshare_requester_db,
+            )?,
+            SwarmInput::Listeners(resp_channel) => {
+                input::handle_listeners_input(resp_channel, swarm)?
+            }
+            SwarmInput::ActiveSessions(resp_channel) => {
+                let _ = resp_channel.send(active_sessions());
+            }
+            SwarmInput::KeyPackage(public_key, resp_channel) => {
+                input::handle_key_package_input(public_key, resp_channel, &database)?
+            }
+            SwarmInput::PublicKeyPackage(public_key, resp_channel) => {
+                input::handle_public_key_package_input(public_key, resp_channel, &database)?
+            }
+            SwarmInput::SignerConfig(public_key, resp_channel) => {
+                input::handle_signer_config_input(public_key, resp_channel, &database)?
+            }
+            SwarmInput::ListKeys(resp_channel) => {
+                input::handle_list_keys_input(resp_channel, &database)?
+            }
+            SwarmInput::ParticipantMap(public_key, resp_channel) => {
+                input::handle_participant_map_input(public_key, resp_channel, &database)?
+            }
+            SwarmInput::ExportGroup(public_key, resp_channel) => {
+                input::handle_export_group_input(public_key, resp_channel, &database)?
+            }
+            SwarmInput::ImportGroup(bundle, resp_channel) => {
+                input::handle_import_group_input(*bundle, resp_channel, swarm, &database)?
+            }
+            SwarmInput::GroupStatus(public_key, resp_channel) => {
+                input::handle_group_status_input(public_key, resp_channel, swarm, &database)?
+            }
+            SwarmInput::Health(resp_channel) => input::handle_health_input(
+                active_sessions(),
+                accepting.load(Ordering::Relaxed),
+                resp_channel,
+                swarm,
                 &database,
             )?,
+            SwarmInput::SetAccepting(value) => {
+                info!(accepting = value, "updated session acceptance");
+                accepting.store(value, Ordering::Relaxed);
+            }
+            SwarmInput::Shutdown(resp_channel) => {
+                // THE MAIN LOOP WINDS DOWN ONCE THIS INPUT IS HANDLED
+                *shutdown_request
+                    .lock()
+                    .map_err(|_| SwarmError::DatabaseError)? = Some(resp_channel);
+            }
         }
         Ok(())
     };
 
     // HANDLE EVENTS FROM SWARM
-    let handle_gossipsub_message = |message: gossipsub::Message,
-                                    swarm: &mut Libp2pSwarm<Behaviour>|
+    let generation_request_for = |topic: &TopicHash| -> Option<QueryId> {
+        generation_requester_db
+            .iter()
+            .find(|entry| entry.topic == *topic)
+            .map(|entry| entry.key().clone())
+    };
+    let handle_protocol_message = |source: PeerId,
+                                   message_data: MessageData,
+                                   topic: TopicHash,
+                                   swarm: &mut Libp2pSwarm<Behaviour>|
      -> Result<(), SwarmError> {
-        let message_data = bincode::deserialize::<MessageData>(&message.data)
-            .map_err(|_| SwarmError::MessageProcessingError)?;
+        let requested_generation = generation_request_for(&topic);
         match message_data {
             MessageData::Generation(genmessage) => {
-                gen::handle_generation_msg(
+                if let Some(query_id) = &requested_generation {
+                    input::handle_generation_progress(
+                        output.clone(),
+                        &generation_requester_db,
+                        &genmessage,
+                        query_id,
+                    )?;
+                }
+                // THE REQUESTER ALSO RUNS A GENERATOR FOR ITS OWN SHARE, WHICH MAY ALREADY BE
+                // GONE WHEN ITS OWN ABORT LOOPS BACK
+                let generating = generator_db.iter().any(|entry| entry.topic == topic);
+                let local = source == *swarm.local_peer_id();
+                if generating || (requested_generation.is_none() && !local) {
+                    gen::handle_generation_msg(
+                        output.clone(),
+                        database.clone(),
+                        swarm,
+                        generator_db.clone(),
+                        genmessage,
+                        source,
+                        topic,
+                    )?;
+                }
+            }
+            MessageData::Presigning(PresignMessage::Request(query_id, count)) => {
+                handle_presign_request(
+                    database.clone(),
+                    swarm,
+                    &presigner_db,
+                    source,
+                    topic,
+                    query_id,
+                    count,
+                    &settings,
+                )?;
+            }
+            MessageData::Presigning(PresignMessage::Sign(
+                query_id,
+                session_id,
+                signing_package,
+                contextual,
+            )) => {
+                handle_presigned_sign(
+                    database.clone(),
+                    swarm,
+                    signer_db.clone(),
+                    &presigner_db,
+                    source,
+                    topic,
+                    query_id,
+                    session_id,
+                    *signing_package,
+                    contextual,
+                    &settings,
+                )?;
+            }
+            MessageData::Signing(SigningMessage::SignFinal(query_id, identifier, signature)) => {
+                if signer_requester_db.contains_key(&query_id) {
+                    sign::partial_signature(
+                        output.clone(),
+                        &signer_requester_db,
+                        query_id.clone(),
+                        identifier,
+                        signature,
+                        topic.clone(),
+                    )?;
+                }
+                // A REQUESTER THAT SIGNS ITSELF AGGREGATES LIKE ANY OTHER SIGNER
+                sign::handle_signing_msg(
                     database.clone(),
                     swarm,
-                    generator_db.clone(),
-                    genmessage,
-                    message.source.ok_or(SwarmError::MessageProcessingError)?,
-                    message.topic,
+                    signer_db.clone(),
+                    SigningMessage::SignFinal(query_id, identifier, signature),
+                    source,
+                    topic,
+                    &settings,
+                )?;
+            }
+            MessageData::Signing(SigningMessage::SignResult(query_id, signature))
+                if signer_requester_db.contains_key(&query_id) =>
+            {
+                send_signature(
+                    output.clone(),
+                    &signer_requester_db,
+                    source,
+                    query_id,
+                    signature,
                 )?;
             }
             MessageData::Signing(signmessage) => {
@@ -131,25 +497,110 @@ async fn start_swarm(
                     swarm,
                     signer_db.clone(),
                     signmessage,
-                    message.source.ok_or(SwarmError::MessageProcessingError)?,
-                    message.topic,
+                    source,
+                    topic,
+                    &settings,
                 )?;
             }
         }
         Ok(())
     };
+    let handle_gossipsub_message = |propagation_source: PeerId,
+                                    message: gossipsub::Message,
+                                    swarm: &mut Libp2pSwarm<Behaviour>|
+     -> Result<(), SwarmError> {
+        let source = message.source.unwrap_or(propagation_source);
+        if !settings.permits(&source) || !settings.permits(&propagation_source) {
+            return Err(SwarmError::InvalidPeer);
+        }
+        if message.data.len() > MAX_MESSAGE_SIZE {
+            return Err(SwarmError::MalformedMessage(source));
+        }
+        if let Some(topic) = message.topic.as_str().strip_prefix(APP_TOPIC_PREFIX) {
+            let mut output = output.clone();
+            let _ = output.start_send(SwarmOutput::Message {
+                topic: topic.to_string(),
+                source: message.source,
+                data: message.data,
+            });
+            return Ok(());
+        }
+        // AN ANONYMOUS MESSAGE ONLY NAMES ITS RELAY, WHICH IS NOT WHO WROTE IT
+        let Some(source) = message.source else {
+            return Err(SwarmError::InvalidPeer);
+        };
+        let message_data = decode_message(source, &message.data)?;
+        if let MessageData::Signing(SigningMessage::SignR1(query_id))
+        | MessageData::Presigning(PresignMessage::Request(query_id, _)) = &message_data
+        {
+            if !rate_limiter.check(source) {
+                warn!(peer = %source, "rate limited signing request");
+                return Err(SwarmError::RateLimited);
+            }
+            if !accepting.load(Ordering::Relaxed) {
+                let _ = swarm
+                    .behaviour_mut()
+                    .req_res
+                    .send_request(&source, DirectMsgData::Decline(query_id.clone()));
+                return Ok(());
+            }
+        }
+        handle_protocol_message(source, message_data, message.topic, swarm)
+    };
     let handle_gossipsub_event =
         |event: GossipsubEvent, swarm: &mut Libp2pSwarm<Behaviour>| -> Result<(), SwarmError> {
             match event {
-                GossipsubEvent::Message { message, .. } => {
-                    handle_gossipsub_message(message, swarm)?;
+                GossipsubEvent::Message {
+                    propagation_source,
+                    message_id,
+                    message,
+                } => {
+                    #[cfg(feature = "metrics")]
+                    metrics::increment(&settings.metrics.gossipsub_messages_in);
+                    let author = message.source;
+                    let acceptance = settings
+                        .message_validator
+                        .as_ref()
+                        .map_or(MessageAcceptance::Accept, |validator| validator(&message));
+                    let result = match acceptance {
+                        MessageAcceptance::Accept => {
+                            handle_gossipsub_message(propagation_source, message, swarm)
+                        }
+                        _ => Ok(()),
+                    };
+                    let acceptance = match &result {
+                        Err(e) if e.is_misbehaviour() => MessageAcceptance::Reject,
+                        Err(SwarmError::RateLimited) => MessageAcceptance::Ignore,
+                        _ => acceptance,
+                    };
+                    if let (MessageAcceptance::Reject, Some(author)) = (&acceptance, author) {
+                        peer_scorer.penalize(&mut swarm.behaviour_mut().gossipsub, &author);
+                    }
+                    if settings.message_validator.is_some() {
+                        let _ = swarm
+                            .behaviour_mut()
+                            .gossipsub
+                            .report_message_validation_result(
+                                &message_id,
+                                &propagation_source,
+                                acceptance,
+                            );
+                    }
+                    result?;
                 }
                 GossipsubEvent::Subscribed { topic, peer_id } => {
-                    if generation_requester_db.contains_key(&topic.to_string()) {
+                    if let Some(query_id) = generation_request_for(&topic) {
                         let mut generation_requester = generation_requester_db
-                            .get_mut(&topic.to_string())
+                            .get_mut(&query_id)
                             .ok_or(SwarmError::DatabaseError)?;
                         let count = generation_requester.insert_response(peer_id)?;
+                        let mut output = output.clone();
+                        let _ = output.start_send(SwarmOutput::GenerationProgress(
+                            generation_requester.origin.clone(),
+                            Round::Discovery,
+                            count,
+                            generation_requester.signer_config.max_signers as usize,
+                        ));
                         if count >= generation_requester.signer_config.max_signers as usize {
                             generation_requester.gen_r2(swarm)?;
                         }
@@ -160,36 +611,119 @@ async fn start_swarm(
             }
             Ok(())
         };
-    let handle_request_event = |message: DirectMsgData,
-                                _channel: ResponseChannel<Vec<u8>>,
-                                swarm: &mut Libp2pSwarm<Behaviour>|
+    let handle_direct_message = |peer: PeerId,
+                                 message: DirectMsgData,
+                                 swarm: &mut Libp2pSwarm<Behaviour>|
      -> Result<(), SwarmError> {
         match message {
-            DirectMsgData::GenStart(query_id, signer_config, participant_id) => {
+            DirectMsgData::Decline(query_id) => {
+                input::handle_declined_invitation(
+                    output.clone(),
+                    peer,
+                    query_id,
+                    swarm,
+                    &generation_requester_db,
+                    &signer_requester_db,
+                )?;
+            }
+            DirectMsgData::GenStart(query_id, signer_config) => {
                 gen_start(
                     &generator_db,
                     swarm,
+                    peer,
                     query_id,
                     signer_config,
-                    participant_id,
+                    settings.topic_hashing,
+                )?;
+            }
+            DirectMsgData::ReshareFinish(query_id) => {
+                handle_reshare_finish(database.clone(), swarm, &resharer_db, peer, query_id)?;
+            }
+            DirectMsgData::ReshareShare(query_id, dealer, share) => {
+                handle_reshare_share(swarm, &resharer_db, peer, query_id, dealer, share)?;
+            }
+            DirectMsgData::ReshareStart(query_id, reshare_config) => {
+                reshare_start(
+                    database.clone(),
+                    swarm,
+                    &resharer_db,
+                    peer,
+                    query_id,
+                    reshare_config,
+                )?;
+            }
+            DirectMsgData::Ping(_) => {}
+            DirectMsgData::PresignCommitments(query_id, identifier, commitments) => {
+                presign_commitments(
+                    &presign_requester_db,
+                    &presigned_db,
+                    peer,
+                    query_id,
+                    identifier,
+                    commitments,
                 )?;
             }
             DirectMsgData::ReturnGen(query_id, pubkey_package) => {
+                #[cfg(feature = "metrics")]
+                let requested = generation_requester_db.contains_key(&query_id);
                 send_final_gen(
                     output.clone(),
+                    swarm,
                     &generation_requester_db,
+                    peer,
+                    query_id.clone(),
+                    pubkey_package,
+                )?;
+                #[cfg(feature = "metrics")]
+                if requested && !generation_requester_db.contains_key(&query_id) {
+                    metrics::increment(&settings.metrics.generations_completed);
+                }
+            }
+            DirectMsgData::ReturnReshare(query_id, pubkey_package) => {
+                send_final_reshare(
+                    swarm,
+                    &reshare_requester_db,
                     database.clone(),
+                    peer,
                     query_id,
                     pubkey_package,
                 )?;
             }
             DirectMsgData::ReturnSign(query_id, signature) => {
-                send_signature(output.clone(), &signer_requester_db, query_id, signature)?;
+                #[cfg(feature = "metrics")]
+                let started = signer_requester_db
+                    .get(&query_id)
+                    .map(|signer_requester| signer_requester.started);
+                send_signature(
+                    output.clone(),
+                    &signer_requester_db,
+                    peer,
+                    query_id.clone(),
+                    signature,
+                )?;
+                #[cfg(feature = "metrics")]
+                if let (Some(started), false) =
+                    (started, signer_requester_db.contains_key(&query_id))
+                {
+                    settings.metrics.record_signing_latency(started.elapsed());
+                }
+            }
+            DirectMsgData::SignRequest(query_id, public_key) => {
+                sign_request(
+                    database.clone(),
+                    swarm,
+                    signer_db.clone(),
+                    peer,
+                    query_id,
+                    public_key,
+                    &settings,
+                )?;
             }
             DirectMsgData::SigningPackage(query_id, identifier, signing_commitments) => {
                 signing_package(
                     &signer_requester_db,
                     swarm,
+                    peer,
                     query_id,
                     identifier,
                     signing_commitments,
@@ -198,36 +732,172 @@ async fn start_swarm(
         }
         Ok(())
     };
-    let handle_behavior_event =
-        |event: BehaviourEvent, swarm: &mut Libp2pSwarm<Behaviour>| -> Result<(), SwarmError> {
-            match event {
-                BehaviourEvent::Gossipsub(event) => {
-                    handle_gossipsub_event(event, swarm)?;
+    let handle_request_event = |peer: PeerId,
+                                message: DirectMsgData,
+                                channel: ResponseChannel<Vec<u8>>,
+                                swarm: &mut Libp2pSwarm<Behaviour>|
+     -> Result<(), SwarmError> {
+        if !settings.permits(&peer) {
+            return Err(SwarmError::InvalidPeer);
+        }
+        let _ = swarm
+            .behaviour_mut()
+            .req_res
+            .send_response(channel, Vec::new());
+        let initiation = matches!(
+            message,
+            DirectMsgData::GenStart(..)
+                | DirectMsgData::ReshareStart(..)
+                | DirectMsgData::SignRequest(..)
+        );
+        if initiation && !rate_limiter.check(peer) {
+            warn!(%peer, "rate limited inbound request");
+            return Err(SwarmError::RateLimited);
+        }
+        // A DRAINING NODE TURNS DOWN NEW SESSIONS BUT KEEPS SERVING RUNNING ONES
+        if let DirectMsgData::GenStart(query_id, _) | DirectMsgData::SignRequest(query_id, _) =
+            &message
+        {
+            if !accepting.load(Ordering::Relaxed) {
+                let _ = swarm
+                    .behaviour_mut()
+                    .req_res
+                    .send_request(&peer, DirectMsgData::Decline(query_id.clone()));
+                return Ok(());
+            }
+        }
+        handle_direct_message(peer, message, swarm)
+    };
+    let handle_loopback =
+        |loopback: Loopback, swarm: &mut Libp2pSwarm<Behaviour>| -> Result<(), SwarmError> {
+            // THE LOCAL NODE IS TRUSTED, SO NONE OF THE CHECKS ON REMOTE TRAFFIC APPLY
+            let local_peer_id = *swarm.local_peer_id();
+            match loopback {
+                Loopback::Message(topic, data) => {
+                    let message_data = decode_message(local_peer_id, &data)?;
+                    handle_protocol_message(local_peer_id, message_data, topic, swarm)
                 }
-                BehaviourEvent::Identify(event) => {
-                    if let identify::Event::Received { peer_id, info } = event {
-                        swarm
-                            .behaviour_mut()
-                            .kad
-                            .add_address(&peer_id, info.listen_addrs[0].clone());
+                Loopback::Request(message) => handle_direct_message(local_peer_id, *message, swarm),
+            }
+        };
+    let handle_behavior_event = |event: BehaviourEvent,
+                                 swarm: &mut Libp2pSwarm<Behaviour>|
+     -> Result<(), SwarmError> {
+        match event {
+            BehaviourEvent::Autonat(autonat::Event::StatusChanged { new, .. }) => {
+                // A NODE BEHIND A NAT CANNOT ANSWER DHT QUERIES, SO IT ONLY ISSUES THEM
+                let mode = match new {
+                    NatStatus::Private => kad::Mode::Client,
+                    NatStatus::Public(_) | NatStatus::Unknown => kad::Mode::Server,
+                };
+                info!(status = ?new, ?mode, "nat status changed");
+                swarm.behaviour_mut().kad.set_mode(Some(mode));
+                let mut output = output.clone();
+                let _ = output.start_send(SwarmOutput::NatStatus(new));
+            }
+            BehaviourEvent::Autonat(_) | BehaviourEvent::Dcutr(_) | BehaviourEvent::Relay(_) => {}
+            BehaviourEvent::Gossipsub(event) => {
+                handle_gossipsub_event(event, swarm)?;
+            }
+            BehaviourEvent::Identify(event) => {
+                if let identify::Event::Received { peer_id, info } = event {
+                    if info.protocol_version != settings.protocol_version {
+                        warn!(
+                            peer = %peer_id,
+                            version = %info.protocol_version,
+                            "disconnecting peer with incompatible protocol version"
+                        );
+                        swarm.behaviour_mut().kad.remove_peer(&peer_id);
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                        return Err(SwarmError::InvalidPeer);
+                    }
+                    for address in info.listen_addrs {
+                        swarm.behaviour_mut().kad.add_address(&peer_id, address);
                     }
                 }
-                BehaviourEvent::Kademlia(_) => {}
-                BehaviourEvent::RequestResponse(event) => {
-                    if let request_response::Event::Message {
-                        message:
-                            ReqResMessage::Request {
-                                request, channel, ..
-                            },
+            }
+            BehaviourEvent::Kademlia(event) => {
+                let mut output = output.clone();
+                match event {
+                    kad::Event::RoutingUpdated {
+                        peer,
+                        addresses,
+                        old_peer,
                         ..
-                    } = event
-                    {
-                        handle_request_event(request, channel, swarm)?;
+                    } => {
+                        let _ = output
+                            .start_send(SwarmOutput::PeerDiscovered(peer, addresses.into_vec()));
+                        if let Some(old_peer) = old_peer {
+                            let _ = output.start_send(SwarmOutput::PeerExpired(old_peer));
+                        }
+                    }
+                    kad::Event::OutboundQueryProgressed {
+                        id,
+                        result: kad::QueryResult::Bootstrap(result),
+                        step,
+                        ..
+                    } if step.last && settings.bootstrap_query == Some(id) => match result {
+                        Ok(_) => {
+                            let _ = output.start_send(SwarmOutput::BootstrapComplete);
+                        }
+                        Err(_) => return Err(SwarmError::Timeout),
+                    },
+                    kad::Event::RoutablePeer { peer, address } => {
+                        let _ = output.start_send(SwarmOutput::PeerDiscovered(peer, vec![address]));
                     }
+                    _ => {}
                 }
             }
-            Ok(())
-        };
+            BehaviourEvent::RequestResponse(event) => match event {
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        ReqResMessage::Request {
+                            request, channel, ..
+                        },
+                } => {
+                    handle_request_event(peer, request, channel, swarm)?;
+                }
+                request_response::Event::Message {
+                    message: ReqResMessage::Response { request_id, .. },
+                    ..
+                } => {
+                    input::handle_ping_response(
+                        request_id,
+                        swarm,
+                        &signer_requester_db,
+                        &reshare_requester_db,
+                    )?;
+                }
+                request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                } => {
+                    if let request_response::OutboundFailure::DialFailure = error {
+                        input::handle_unreachable_peer(peer, &reshare_requester_db)?;
+                        input::handle_dropped_peer(peer, swarm, &generation_requester_db)?;
+                    }
+                    input::handle_ping_failure(
+                        output.clone(),
+                        request_id,
+                        swarm,
+                        &signer_requester_db,
+                        &reshare_requester_db,
+                    )?;
+                }
+                request_response::Event::InboundFailure {
+                    peer,
+                    error: request_response::InboundFailure::Io(error),
+                    ..
+                } if error.kind() == std::io::ErrorKind::InvalidData => {
+                    return Err(SwarmError::MalformedMessage(peer));
+                }
+                _ => {}
+            },
+        }
+        Ok(())
+    };
     let handle_event = |event: SwarmEvent<BehaviourEvent>,
                         swarm: &mut Libp2pSwarm<Behaviour>|
      -> Result<(), SwarmError> {
@@ -236,32 +906,252 @@ async fn start_swarm(
                 handle_behavior_event(event, swarm)?;
             }
             _ => {
-                let mut output = output.clone();
-                let _ = output.start_send(SwarmOutput::SwarmEvents(event));
+                match &event {
+                    SwarmEvent::ConnectionEstablished {
+                        peer_id,
+                        connection_id,
+                        ..
+                    } => {
+                        if let Some((_, resp_channel)) = pending_dials.remove(connection_id) {
+                            let _ = resp_channel.send(Ok(*peer_id));
+                        }
+                        input::notify_peer_waiters(swarm, &peer_waiters)?;
+                    }
+                    SwarmEvent::ConnectionClosed {
+                        peer_id,
+                        num_established: 0,
+                        ..
+                    } => {
+                        input::handle_dropped_peer(*peer_id, swarm, &generation_requester_db)?;
+                    }
+                    SwarmEvent::OutgoingConnectionError { connection_id, .. } => {
+                        if let Some((_, resp_channel)) = pending_dials.remove(connection_id) {
+                            let _ = resp_channel.send(Err(SwarmError::DialFailure));
+                        }
+                    }
+                    _ => {}
+                }
+                let denied = match &event {
+                    SwarmEvent::IncomingConnectionError {
+                        error: ListenError::Denied { cause },
+                        ..
+                    }
+                    | SwarmEvent::OutgoingConnectionError {
+                        error: DialError::Denied { cause },
+                        ..
+                    } => {
+                        cause
+                            .downcast_ref::<allow_block_list::NotAllowed>()
+                            .is_some()
+                            || cause.downcast_ref::<allow_block_list::Blocked>().is_some()
+                    }
+                    _ => false,
+                };
+                if settings.event_filter.allows(&event) {
+                    let mut output = output.clone();
+                    let _ = output.start_send(SwarmOutput::SwarmEvents(event));
+                }
+                if denied {
+                    return Err(SwarmError::InvalidPeer);
+                }
             }
         }
         Ok(())
     };
 
+    // HANDLE PERIODIC MAINTENANCE
+    let handle_tick = |swarm: &mut Libp2pSwarm<Behaviour>| -> Result<(), SwarmError> {
+        #[cfg(feature = "metrics")]
+        let pending_generations = generation_requester_db.len();
+        input::expire_requests(
+            output.clone(),
+            swarm,
+            &settings.timeouts,
+            &generation_requester_db,
+            &signer_requester_db,
+            &presign_requester_db,
+            &reshare_requester_db,
+        )?;
+        #[cfg(feature = "metrics")]
+        {
+            let expired = pending_generations - generation_requester_db.len();
+            settings
+                .metrics
+                .generations_failed
+                .fetch_add(expired as u64, Ordering::Relaxed);
+            settings
+                .metrics
+                .connected_peers
+                .store(swarm.connected_peers().count() as u64, Ordering::Relaxed);
+        }
+        input::notify_peer_waiters(swarm, &peer_waiters)?;
+        if let Some(interval) = settings.topology_interval {
+            input::emit_topology(output.clone(), interval, swarm, &last_topology)?;
+        }
+        input::abandon_canceled_requests(
+            swarm,
+            &generation_requester_db,
+            &signer_requester_db,
+            &presign_requester_db,
+            &reshare_requester_db,
+        )?;
+        if let Some(liveness) = settings.timeouts.liveness {
+            input::ping_signers(liveness, swarm, &signer_requester_db);
+        }
+        input::release_request_topics(
+            swarm,
+            &signer_requester_db,
+            &presign_requester_db,
+            &database,
+        );
+        gen::expire_generators(swarm, settings.timeouts.round, &generator_db)?;
+        sign::expire_signers(settings.timeouts.round, &signer_db)?;
+        presign::expire_presigned(settings.timeouts.presign, &presigner_db, &presigned_db)?;
+        reshare::expire_resharers(settings.timeouts.round, &resharer_db)?;
+        rate_limiter.prune();
+        peer_scorer.decay(&mut swarm.behaviour_mut().gossipsub);
+        Ok(())
+    };
+
     // BEGIN MAIN LOOP
-    loop {
+    let mut tick = Delay::new(TICK_INTERVAL).fuse();
+    let shutdown_channel = loop {
         select! {
-            recv = input.next().fuse() => {
-                if let Some(recv) = recv {
+            recv = input.next().fuse() => match recv {
+                Some(recv) => {
                     handle_client_input(recv, &mut swarm).unwrap_or_else(|e| {
+                        #[cfg(feature = "metrics")]
+                        metrics::increment(&settings.metrics.errors);
                         let mut output = output.clone();
                         let _ = output.start_send(SwarmOutput::Error(e));
                     });
+                    if let Some(resp_channel) = shutdown_request
+                        .lock()
+                        .ok()
+                        .and_then(|mut shutdown_request| shutdown_request.take())
+                    {
+                        break Some(resp_channel);
+                    }
                 }
+                None => break None,
             },
             event = swarm.next().fuse() => {
                 if let Some(event) = event {
                     handle_event(event, &mut swarm).unwrap_or_else(|e| {
+                        #[cfg(feature = "metrics")]
+                        metrics::increment(&settings.metrics.errors);
                         let mut output = output.clone();
                         let _ = output.start_send(SwarmOutput::Error(e));
                     });
                 }
             },
+            _ = tick => {
+                handle_tick(&mut swarm).unwrap_or_else(|e| {
+                    #[cfg(feature = "metrics")]
+                    metrics::increment(&settings.metrics.errors);
+                    let mut output = output.clone();
+                    let _ = output.start_send(SwarmOutput::Error(e));
+                });
+                tick = Delay::new(TICK_INTERVAL).fuse();
+            },
+        }
+        while let Some(loopback) = swarm.behaviour_mut().req_res.take_loopback() {
+            handle_loopback(loopback, &mut swarm).unwrap_or_else(|e| {
+                #[cfg(feature = "metrics")]
+                metrics::increment(&settings.metrics.errors);
+                let mut output = output.clone();
+                let _ = output.start_send(SwarmOutput::Error(e));
+            });
+        }
+    };
+
+    // CANCEL IN-FLIGHT QUERIES AND FLUSH ABORT MESSAGES BEFORE CLOSING
+    input::handle_shutdown_input(
+        &mut swarm,
+        &listeners,
+        &generation_requester_db,
+        &signer_requester_db,
+        &presign_requester_db,
+        &reshare_requester_db,
+    )
+    .unwrap_or_else(|e| {
+        let mut output = output.clone();
+        let _ = output.start_send(SwarmOutput::Error(e));
+    });
+    if let Some(peer_cache) = &settings.peer_cache {
+        utils::save_peer_cache(&mut swarm, peer_cache).unwrap_or_else(|e| {
+            let mut output = output.clone();
+            let _ = output.start_send(SwarmOutput::Error(e));
+        });
+    }
+    let mut grace = Delay::new(TICK_INTERVAL).fuse();
+    loop {
+        select! {
+            event = swarm.next().fuse() => {
+                if let Some(event) = event {
+                    let _ = handle_event(event, &mut swarm);
+                }
+            },
+            _ = grace => break,
+        }
+    }
+    if let Some(shutdown_channel) = shutdown_channel {
+        let _ = shutdown_channel.send(());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn assert_decodes_or_rejects(source: PeerId, data: &[u8]) {
+        match decode_message(source, data) {
+            Ok(_) => {}
+            Err(SwarmError::MalformedMessage(peer)) => assert_eq!(peer, source),
+            Err(e) => panic!("unexpected error for {data:?}: {e}"),
+        }
+    }
+
+    #[test]
+    fn decode_message_rejects_random_bytes() {
+        let mut rng = StdRng::seed_from_u64(72);
+        let source = PeerId::random();
+        for _ in 0..10_000 {
+            let len = rng.gen_range(0..512);
+            let data = (0..len).map(|_| rng.gen::<u8>()).collect::<Vec<_>>();
+            assert_decodes_or_rejects(source, &data);
+        }
+        let data = (0..MAX_MESSAGE_SIZE)
+            .map(|_| rng.gen::<u8>())
+            .collect::<Vec<_>>();
+        assert_decodes_or_rejects(source, &data);
+    }
+
+    #[test]
+    fn decode_message_rejects_mutated_messages() {
+        let mut rng = StdRng::seed_from_u64(72);
+        let source = PeerId::random();
+        let messages = [
+            MessageData::Generation(GenerationMessage::GenR1),
+            MessageData::Signing(SigningMessage::SignR1("query".to_string())),
+            MessageData::Presigning(PresignMessage::Request("query".to_string(), 8)),
+        ];
+        for message in &messages {
+            let encoded = bincode::serialize(message).unwrap();
+            assert!(decode_message(source, &encoded).is_ok());
+            for _ in 0..2_000 {
+                let mut data = encoded.clone();
+                for _ in 0..rng.gen_range(1..4) {
+                    let index = rng.gen_range(0..data.len());
+                    data[index] = rng.gen();
+                }
+                data.truncate(rng.gen_range(0..=data.len()));
+                data.extend((0..rng.gen_range(0..16)).map(|_| rng.gen::<u8>()));
+                assert_decodes_or_rejects(source, &data);
+            }
         }
     }
 }
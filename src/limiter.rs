@@ -0,0 +1,48 @@
+use std::time::Instant;
+
+use dashmap::DashMap;
+use libp2p::PeerId;
+
+use crate::swarm::RateLimit;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub(crate) struct RateLimiter {
+    buckets: DashMap<PeerId, Bucket>,
+    config: RateLimit,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimit) -> Self {
+        Self {
+            buckets: DashMap::new(),
+            config,
+        }
+    }
+
+    pub(crate) fn check(&self, peer: PeerId) -> bool {
+        let burst = self.config.burst as f64;
+        let mut bucket = self.buckets.entry(peer).or_insert_with(|| Bucket {
+            tokens: burst,
+            updated: Instant::now(),
+        });
+        let refill = bucket.updated.elapsed().as_secs_f64() * self.config.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(burst);
+        bucket.updated = Instant::now();
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    pub(crate) fn prune(&self) {
+        let burst = self.config.burst as f64;
+        self.buckets.retain(|_, bucket| {
+            bucket.tokens + bucket.updated.elapsed().as_secs_f64() * self.config.per_second < burst
+        });
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use libp2p::gossipsub::{DataTransform, Message, RawMessage, TopicHash};
+
+use crate::bandwidth::Bandwidth;
+
+const LATENCY_BUCKETS: [Duration; 8] = [
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(30),
+    Duration::from_secs(120),
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct MetricsSnapshot {
+    pub generations_started: u64,
+    pub generations_completed: u64,
+    pub generations_failed: u64,
+    pub signings_completed: u64,
+    pub signing_latency: LatencyHistogram,
+    pub gossipsub_messages_in: u64,
+    pub gossipsub_messages_out: u64,
+    pub connected_peers: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub errors: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LatencyHistogram {
+    pub buckets: Vec<(Duration, u64)>,
+    pub count: u64,
+    pub sum: Duration,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) generations_started: AtomicU64,
+    pub(crate) generations_completed: AtomicU64,
+    pub(crate) generations_failed: AtomicU64,
+    pub(crate) signings_completed: AtomicU64,
+    pub(crate) gossipsub_messages_in: AtomicU64,
+    pub(crate) gossipsub_messages_out: AtomicU64,
+    pub(crate) connected_peers: AtomicU64,
+    pub(crate) errors: AtomicU64,
+    pub(crate) bandwidth: Arc<Bandwidth>,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+}
+
+pub(crate) fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Metrics {
+    pub(crate) fn record_signing_latency(&self, latency: Duration) {
+        for (bucket, counter) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            if latency <= *bucket {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.signings_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let bandwidth = self.bandwidth.total();
+        MetricsSnapshot {
+            generations_started: load(&self.generations_started),
+            generations_completed: load(&self.generations_completed),
+            generations_failed: load(&self.generations_failed),
+            signings_completed: load(&self.signings_completed),
+            signing_latency: LatencyHistogram {
+                buckets: LATENCY_BUCKETS
+                    .iter()
+                    .zip(&self.latency_buckets)
+                    .map(|(bucket, counter)| (*bucket, load(counter)))
+                    .collect(),
+                count: load(&self.latency_count),
+                sum: Duration::from_micros(load(&self.latency_sum_micros)),
+            },
+            gossipsub_messages_in: load(&self.gossipsub_messages_in),
+            gossipsub_messages_out: load(&self.gossipsub_messages_out),
+            connected_peers: load(&self.connected_peers),
+            bytes_in: bandwidth.inbound,
+            bytes_out: bandwidth.outbound,
+            errors: load(&self.errors),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct CountingTransform {
+    pub(crate) metrics: Arc<Metrics>,
+}
+
+impl DataTransform for CountingTransform {
+    fn inbound_transform(&self, raw_message: RawMessage) -> Result<Message, std::io::Error> {
+        Ok(Message {
+            source: raw_message.source,
+            data: raw_message.data,
+            sequence_number: raw_message.sequence_number,
+            topic: raw_message.topic,
+        })
+    }
+
+    fn outbound_transform(
+        &self,
+        _topic: &TopicHash,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        increment(&self.metrics.gossipsub_messages_out);
+        Ok(data)
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine as Base64Engine};
+use dashmap::DashMap;
+use frost_ed25519::{round1, Identifier, SigningPackage};
+use libp2p::{gossipsub::TopicHash, PeerId, Swarm as Libp2pSwarm};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::swarm::{Settings, SwarmError};
+use crate::{
+    database::Database,
+    input::ReqPresign,
+    sign::{handle_r2_signing, seeded_rng, Signer},
+    utils::check_context,
+    Behaviour, DirectMsgData, QueryId,
+};
+
+pub(crate) const MAX_PRESIGN_BATCH: u16 = 64;
+
+#[derive(Deserialize, Serialize)]
+pub(crate) enum PresignMessage {
+    Request(QueryId, u16),
+    Sign(QueryId, QueryId, Box<SigningPackage>, bool),
+}
+
+// A NONCE HELD BY A PARTICIPANT, REMOVED BEFORE IT IS EVER USED TO SIGN
+pub(crate) struct Presigner {
+    created: Instant,
+    nonces: round1::SigningNonces,
+    requester: PeerId,
+    topic: TopicHash,
+}
+
+// THE MATCHING COMMITMENTS HELD BY THE REQUESTER
+pub(crate) struct Presigned {
+    pub(crate) commitments: BTreeMap<Identifier, round1::SigningCommitments>,
+    created: Instant,
+    pub(crate) peers: BTreeMap<Identifier, PeerId>,
+    pub(crate) session_id: QueryId,
+}
+
+pub(crate) fn session_id(query_id: &QueryId, index: u16) -> QueryId {
+    format!("{query_id}-{index}")
+}
+
+/// Pops the oldest presigned session for `pubkey`, so that no session is handed out twice.
+pub(crate) fn take_presigned(
+    presigned_db: &DashMap<Vec<u8>, Vec<Presigned>>,
+    pubkey: &Vec<u8>,
+) -> Option<Presigned> {
+    let mut presigned = presigned_db.get_mut(pubkey)?;
+    if presigned.is_empty() {
+        return None;
+    }
+    Some(presigned.remove(0))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_presign_request(
+    database: Arc<Database>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    presigner_db: &DashMap<QueryId, Presigner>,
+    propagation_source: PeerId,
+    topic: TopicHash,
+    query_id: QueryId,
+    count: u16,
+    settings: &Settings,
+) -> Result<(), SwarmError> {
+    if count == 0 || count > MAX_PRESIGN_BATCH {
+        return Err(SwarmError::MalformedMessage(propagation_source));
+    }
+    if presigner_db.contains_key(&session_id(&query_id, 0)) {
+        return Ok(());
+    }
+    let data = database
+        .get(
+            &b64.decode(topic.as_str())
+                .map_err(|_| SwarmError::MessageProcessingError)?,
+        )
+        .ok_or(SwarmError::DatabaseError)?
+        .clone();
+    // ONLY SHARE HOLDERS PRESIGN, A REQUESTER MAY MERELY FOLLOW THE GROUP
+    let Some(key_package) = data.key_package else {
+        return Ok(());
+    };
+    let identifier = data.identifier.ok_or(SwarmError::DatabaseError)?;
+    let mut commitments = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let session_id = session_id(&query_id, index);
+        let (nonces, commitment) = match settings.rng_seed {
+            Some(seed) => round1::commit(
+                key_package.signing_share(),
+                &mut seeded_rng(seed, &session_id, identifier),
+            ),
+            None => round1::commit(key_package.signing_share(), &mut rand::rngs::OsRng),
+        };
+        presigner_db.insert(
+            session_id,
+            Presigner {
+                created: Instant::now(),
+                nonces,
+                requester: propagation_source,
+                topic: topic.clone(),
+            },
+        );
+        commitments.push(commitment);
+    }
+    debug!(query_id = %query_id, requester = %propagation_source, count, "presigning");
+    let _ = swarm.behaviour_mut().req_res.send_request(
+        &propagation_source,
+        DirectMsgData::PresignCommitments(query_id, identifier, commitments),
+    );
+    Ok(())
+}
+
+pub(crate) fn presign_commitments(
+    presign_requester_db: &DashMap<QueryId, ReqPresign>,
+    presigned_db: &DashMap<Vec<u8>, Vec<Presigned>>,
+    peer: PeerId,
+    query_id: QueryId,
+    identifier: Identifier,
+    commitments: Vec<round1::SigningCommitments>,
+) -> Result<(), SwarmError> {
+    let Some(mut presign_requester) = presign_requester_db.get_mut(&query_id) else {
+        return Ok(());
+    };
+    if commitments.len() != presign_requester.count as usize {
+        return Err(SwarmError::MalformedMessage(peer));
+    }
+    if presign_requester.insert_commitments(peer, identifier, commitments)
+        < presign_requester.threshold()
+    {
+        return Ok(());
+    }
+    drop(presign_requester);
+    let (_, presign_requester) = presign_requester_db
+        .remove(&query_id)
+        .ok_or(SwarmError::DatabaseError)?;
+    let mut presigned = presigned_db
+        .entry(presign_requester.pubkey.clone())
+        .or_default();
+    for index in 0..presign_requester.count {
+        let (commitments, peers) = presign_requester.responses.iter().fold(
+            (BTreeMap::new(), BTreeMap::new()),
+            |(mut commitments, mut peers), (identifier, (peer, batch))| {
+                commitments.insert(*identifier, batch[index as usize]);
+                peers.insert(*identifier, *peer);
+                (commitments, peers)
+            },
+        );
+        presigned.push(Presigned {
+            commitments,
+            // COUNT FROM THE REQUEST SO THE REQUESTER EXPIRES BEFORE ANY PARTICIPANT
+            created: presign_requester.started,
+            peers,
+            session_id: session_id(&query_id, index),
+        });
+    }
+    drop(presigned);
+    let count = presign_requester.count as usize;
+    presign_requester.send_response(Ok(count))?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_presigned_sign(
+    database: Arc<Database>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    signer_db: Arc<DashMap<QueryId, Signer>>,
+    presigner_db: &DashMap<QueryId, Presigner>,
+    propagation_source: PeerId,
+    topic: TopicHash,
+    query_id: QueryId,
+    session_id: QueryId,
+    signing_package: SigningPackage,
+    contextual: bool,
+    settings: &Settings,
+) -> Result<(), SwarmError> {
+    if signer_db.contains_key(&query_id) {
+        return Ok(());
+    }
+    // REFUSE BEFORE THE NONCE IS SPENT
+    check_context(signing_package.message(), contextual)?;
+    // THE NONCE LEAVES THE STORE BEFORE SIGNING SO IT CAN NEVER SIGN TWICE
+    let Some((_, presigner)) = presigner_db.remove_if(&session_id, |_, presigner| {
+        presigner.requester == propagation_source && presigner.topic == topic
+    }) else {
+        return Ok(());
+    };
+    let data = database
+        .get(
+            &b64.decode(topic.as_str())
+                .map_err(|_| SwarmError::MessageProcessingError)?,
+        )
+        .ok_or(SwarmError::DatabaseError)?
+        .clone();
+    let signer = Signer::presigned(
+        data,
+        propagation_source,
+        query_id.clone(),
+        topic,
+        settings,
+        presigner.nonces,
+    );
+    debug!(query_id = %query_id, session_id = %session_id, "signing with presigned nonce");
+    signer_db.insert(query_id.clone(), signer);
+    handle_r2_signing(swarm, signer_db, query_id, signing_package, contextual)
+}
+
+pub(crate) fn expire_presigned(
+    ttl: Duration,
+    presigner_db: &DashMap<QueryId, Presigner>,
+    presigned_db: &DashMap<Vec<u8>, Vec<Presigned>>,
+) -> Result<(), SwarmError> {
+    presigner_db.retain(|_, presigner| presigner.created.elapsed() <= ttl);
+    presigned_db.retain(|_, presigned| {
+        presigned.retain(|presigned| presigned.created.elapsed() <= ttl);
+        !presigned.is_empty()
+    });
+    Ok(())
+}
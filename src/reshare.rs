@@ -0,0 +1,365 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine as Base64Engine};
+use dashmap::DashMap;
+use frost_ed25519::{
+    keys::{
+        self, IdentifierList, KeyPackage, PublicKeyPackage, SecretShare, SigningShare,
+        VerifiableSecretSharingCommitment,
+    },
+    Identifier, SigningKey,
+};
+use libp2p::{gossipsub::IdentTopic, PeerId, Swarm as Libp2pSwarm};
+use serde::{Deserialize, Serialize};
+
+use crate::swarm::SwarmError;
+use crate::{
+    database::Database, input::ReqReshare, Behaviour, Ciphersuite, DbData, DirectMsgData, QueryId,
+    SignerConfig,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReshareConfig {
+    pub(crate) dealers: BTreeMap<Identifier, PeerId>,
+    pub(crate) participants: BTreeMap<Identifier, PeerId>,
+    pub(crate) public_key_package: PublicKeyPackage,
+    pub(crate) signer_config: SignerConfig,
+}
+
+impl ReshareConfig {
+    pub(crate) fn pubkey(&self) -> Vec<u8> {
+        self.public_key_package.verifying_key().serialize().to_vec()
+    }
+
+    fn dealer_identifiers(&self) -> BTreeSet<Identifier> {
+        self.dealers.keys().copied().collect()
+    }
+}
+
+pub(crate) struct Resharer {
+    config: Option<ReshareConfig>,
+    key_package: Option<KeyPackage>,
+    last_activity: Instant,
+    propagation_source: Option<PeerId>,
+    public_key_package: Option<PublicKeyPackage>,
+    shares: BTreeMap<Identifier, (PeerId, SecretShare)>,
+}
+
+impl Resharer {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: None,
+            key_package: None,
+            last_activity: Instant::now(),
+            propagation_source: None,
+            public_key_package: None,
+            shares: BTreeMap::new(),
+        }
+    }
+
+    fn deal(
+        &mut self,
+        swarm: &mut Libp2pSwarm<Behaviour>,
+        query_id: &QueryId,
+        key_package: &KeyPackage,
+    ) -> Result<(), SwarmError> {
+        let config = self.config.as_ref().ok_or(SwarmError::DatabaseError)?;
+        let lambda = frost_core::compute_lagrange_coefficient::<Ciphersuite>(
+            &config.dealer_identifiers(),
+            None,
+            *key_package.identifier(),
+        )
+        .map_err(|_| SwarmError::GenerationError)?;
+        let key = SigningKey::from_scalar(lambda * key_package.signing_share().to_scalar());
+        let identifiers = config.participants.keys().copied().collect::<Vec<_>>();
+        let mut rng = rand::rngs::OsRng;
+        let (shares, _) = keys::split(
+            &key,
+            identifiers.len() as u16,
+            config.signer_config.min_signers,
+            IdentifierList::Custom(&identifiers),
+            &mut rng,
+        )
+        .map_err(|_| SwarmError::GenerationError)?;
+        let local_peer_id = *swarm.local_peer_id();
+        let mut local_share = None;
+        for (identifier, share) in shares {
+            let peer = config
+                .participants
+                .get(&identifier)
+                .ok_or(SwarmError::GenerationError)?;
+            if *peer == local_peer_id {
+                local_share = Some(share);
+                continue;
+            }
+            let _ = swarm.behaviour_mut().req_res.send_request(
+                peer,
+                DirectMsgData::ReshareShare(query_id.clone(), *key_package.identifier(), share),
+            );
+        }
+        if let Some(share) = local_share {
+            self.shares
+                .insert(*key_package.identifier(), (local_peer_id, share));
+        }
+        Ok(())
+    }
+
+    fn try_complete(
+        &mut self,
+        swarm: &mut Libp2pSwarm<Behaviour>,
+        query_id: &QueryId,
+    ) -> Result<(), SwarmError> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        if self.key_package.is_some() || self.shares.len() < config.dealers.len() {
+            return Ok(());
+        }
+        let local_peer_id = *swarm.local_peer_id();
+        let Some(identifier) = config
+            .participants
+            .iter()
+            .find_map(|(identifier, peer)| (*peer == local_peer_id).then_some(*identifier))
+        else {
+            return Ok(());
+        };
+        let dealer_identifiers = config.dealer_identifiers();
+        let mut commitments = Vec::<&VerifiableSecretSharingCommitment>::new();
+        for (dealer, dealer_peer) in &config.dealers {
+            let (sender, share) = self.shares.get(dealer).ok_or(SwarmError::GenerationError)?;
+            if sender != dealer_peer {
+                return Err(SwarmError::InvalidPeer);
+            }
+            if *share.identifier() != identifier {
+                return Err(SwarmError::GenerationError);
+            }
+            share.verify().map_err(|_| SwarmError::GenerationError)?;
+            let lambda = frost_core::compute_lagrange_coefficient::<Ciphersuite>(
+                &dealer_identifiers,
+                None,
+                *dealer,
+            )
+            .map_err(|_| SwarmError::GenerationError)?;
+            let expected = config
+                .public_key_package
+                .verifying_shares()
+                .get(dealer)
+                .ok_or(SwarmError::GenerationError)?
+                .to_element()
+                * lambda;
+            let constant = share
+                .commitment()
+                .coefficients()
+                .first()
+                .ok_or(SwarmError::GenerationError)?
+                .value();
+            if constant != expected {
+                return Err(SwarmError::GenerationError);
+            }
+            commitments.push(share.commitment());
+        }
+        let signing_share = self
+            .shares
+            .values()
+            .map(|(_, share)| share.signing_share().to_scalar())
+            .reduce(|sum, scalar| sum + scalar)
+            .ok_or(SwarmError::GenerationError)?;
+        let group_commitment = frost_core::keys::sum_commitments(&commitments)
+            .map_err(|_| SwarmError::GenerationError)?;
+        let pubkey_package = PublicKeyPackage::from_commitment(
+            &config.participants.keys().copied().collect(),
+            &group_commitment,
+        )
+        .map_err(|_| SwarmError::GenerationError)?;
+        if pubkey_package.verifying_key() != config.public_key_package.verifying_key() {
+            return Err(SwarmError::GenerationError);
+        }
+        let verifying_share = *pubkey_package
+            .verifying_shares()
+            .get(&identifier)
+            .ok_or(SwarmError::GenerationError)?;
+        self.key_package = Some(KeyPackage::new(
+            identifier,
+            SigningShare::new(signing_share),
+            verifying_share,
+            *pubkey_package.verifying_key(),
+            config.signer_config.min_signers,
+        ));
+        self.public_key_package = Some(pubkey_package.clone());
+        let _ = swarm.behaviour_mut().req_res.send_request(
+            &self.propagation_source.ok_or(SwarmError::DatabaseError)?,
+            DirectMsgData::ReturnReshare(query_id.clone(), pubkey_package),
+        );
+        Ok(())
+    }
+}
+
+pub(crate) fn reshare_start(
+    database: Arc<Database>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    resharer_db: &DashMap<QueryId, Resharer>,
+    propagation_source: PeerId,
+    query_id: QueryId,
+    config: ReshareConfig,
+) -> Result<(), SwarmError> {
+    let local_peer_id = *swarm.local_peer_id();
+    let data = database.get(&config.pubkey()).map(|data| data.clone());
+    match &data {
+        Some(data) if data.public_key_package.as_ref() != Some(&config.public_key_package) => {
+            return Err(SwarmError::ConfigurationError);
+        }
+        None if !config
+            .participants
+            .values()
+            .any(|participant| *participant == local_peer_id) =>
+        {
+            return Err(SwarmError::DatabaseError);
+        }
+        _ => {}
+    }
+    let mut resharer = resharer_db
+        .entry(query_id.clone())
+        .or_insert_with(Resharer::new);
+    if resharer.propagation_source.is_some() {
+        return Ok(());
+    }
+    resharer.last_activity = Instant::now();
+    resharer.propagation_source = Some(propagation_source);
+    let key_package = data.and_then(|data| data.key_package);
+    let is_dealer = key_package
+        .as_ref()
+        .is_some_and(|key_package| config.dealers.contains_key(key_package.identifier()));
+    resharer.config = Some(config);
+    if let (true, Some(key_package)) = (is_dealer, key_package) {
+        resharer.deal(swarm, &query_id, &key_package)?;
+    }
+    resharer.try_complete(swarm, &query_id)?;
+    Ok(())
+}
+
+pub(crate) fn handle_reshare_share(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    resharer_db: &DashMap<QueryId, Resharer>,
+    propagation_source: PeerId,
+    query_id: QueryId,
+    dealer: Identifier,
+    share: SecretShare,
+) -> Result<(), SwarmError> {
+    let mut resharer = resharer_db
+        .entry(query_id.clone())
+        .or_insert_with(Resharer::new);
+    resharer.last_activity = Instant::now();
+    resharer
+        .shares
+        .entry(dealer)
+        .or_insert((propagation_source, share));
+    resharer.try_complete(swarm, &query_id)?;
+    Ok(())
+}
+
+pub(crate) fn handle_reshare_finish(
+    database: Arc<Database>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    resharer_db: &DashMap<QueryId, Resharer>,
+    propagation_source: PeerId,
+    query_id: QueryId,
+) -> Result<(), SwarmError> {
+    let from_requester = resharer_db
+        .get(&query_id)
+        .is_some_and(|resharer| resharer.propagation_source == Some(propagation_source));
+    if !from_requester {
+        return Err(SwarmError::InvalidPeer);
+    }
+    let (_, resharer) = resharer_db
+        .remove(&query_id)
+        .ok_or(SwarmError::DatabaseError)?;
+    let config = resharer.config.ok_or(SwarmError::DatabaseError)?;
+    let pubkey = config.pubkey();
+    let topic = IdentTopic::new(b64.encode(&pubkey));
+    let local_peer_id = *swarm.local_peer_id();
+    if !config
+        .participants
+        .values()
+        .any(|participant| *participant == local_peer_id)
+    {
+        database.remove(&pubkey)?;
+        let _ = swarm.behaviour_mut().gossipsub.unsubscribe(&topic);
+        return Ok(());
+    }
+    let key_package = resharer.key_package.ok_or(SwarmError::GenerationError)?;
+    database.insert(
+        pubkey,
+        DbData {
+            identifier: Some(*key_package.identifier()),
+            key_package: Some(key_package),
+            participants: Some(config.participants),
+            public_key_package: resharer.public_key_package,
+            signer_config: Some(config.signer_config),
+        },
+    )?;
+    let _ = swarm.behaviour_mut().gossipsub.subscribe(&topic);
+    Ok(())
+}
+
+pub(crate) fn send_final_reshare(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    reshare_requester_db: &DashMap<QueryId, ReqReshare>,
+    database: Arc<Database>,
+    peer: PeerId,
+    query_id: QueryId,
+    pubkey_package: PublicKeyPackage,
+) -> Result<(), SwarmError> {
+    let Some(mut reshare_requester) = reshare_requester_db.get_mut(&query_id) else {
+        return Ok(());
+    };
+    let count = reshare_requester.insert_response(peer, pubkey_package)?;
+    if count < reshare_requester.config.participants.len() {
+        return Ok(());
+    }
+    drop(reshare_requester);
+    let (_, reshare_requester) = reshare_requester_db
+        .remove(&query_id)
+        .ok_or(SwarmError::DatabaseError)?;
+    let mut responses = reshare_requester.responses.values();
+    let pubkey_package = responses.next().ok_or(SwarmError::DatabaseError)?.clone();
+    if responses.any(|response| *response != pubkey_package) {
+        reshare_requester.send_response(Err(SwarmError::GenerationError))?;
+        return Ok(());
+    }
+    reshare_requester.finish(swarm)?;
+    // A REQUESTER THAT TAKES PART STORES ITS NEW SHARE WHEN ITS OWN FINISH LOOPS BACK
+    let local_peer_id = *swarm.local_peer_id();
+    if reshare_requester
+        .config
+        .participants
+        .values()
+        .any(|participant| *participant == local_peer_id)
+    {
+        reshare_requester.send_response(Ok(pubkey_package))?;
+        return Ok(());
+    }
+    database.insert(
+        reshare_requester.config.pubkey(),
+        DbData {
+            identifier: None,
+            key_package: None,
+            participants: Some(reshare_requester.config.participants.clone()),
+            public_key_package: Some(pubkey_package.clone()),
+            signer_config: Some(reshare_requester.config.signer_config.clone()),
+        },
+    )?;
+    reshare_requester.send_response(Ok(pubkey_package))?;
+    Ok(())
+}
+
+pub(crate) fn expire_resharers(
+    round_timeout: Duration,
+    resharer_db: &Arc<DashMap<QueryId, Resharer>>,
+) -> Result<(), SwarmError> {
+    resharer_db.retain(|_, resharer| resharer.last_activity.elapsed() <= round_timeout);
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use dashmap::DashMap;
+use libp2p::PeerId;
+
+use crate::swarm::{Gossipsub, PeerScoring};
+
+// SCORES THIS CLOSE TO ZERO ARE FORGOTTEN
+const DECAY_TO_ZERO: f64 = 0.01;
+
+pub(crate) struct PeerScorer {
+    scores: DashMap<PeerId, f64>,
+    config: Option<PeerScoring>,
+}
+
+impl PeerScorer {
+    pub(crate) fn new(config: Option<PeerScoring>) -> Self {
+        Self {
+            scores: DashMap::new(),
+            config,
+        }
+    }
+
+    pub(crate) fn penalize(&self, gossipsub: &mut Gossipsub, peer: &PeerId) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let mut score = self.scores.entry(*peer).or_insert(0.0);
+        *score -= config.penalty;
+        gossipsub.set_application_score(peer, *score);
+    }
+
+    pub(crate) fn decay(&self, gossipsub: &mut Gossipsub) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        self.scores.retain(|peer, score| {
+            *score *= config.decay;
+            if score.abs() < DECAY_TO_ZERO {
+                *score = 0.0;
+            }
+            gossipsub.set_application_score(peer, *score);
+            *score != 0.0
+        });
+    }
+}
@@ -1,26 +1,48 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use base64::{engine::general_purpose::STANDARD_NO_PAD as b64, Engine as Base64Engine};
 use dashmap::{mapref::one::RefMut, DashMap};
-use frost_ed25519::{round1, round2, Identifier, Signature, SigningPackage, VerifyingKey};
+use frost_ed25519::{
+    keys::PublicKeyPackage, round1, round2, Error as FrostError, Identifier, Signature,
+    SigningPackage, VerifyingKey,
+};
 use futures::channel::mpsc::UnboundedSender;
 use libp2p::{gossipsub::TopicHash, PeerId, Swarm as Libp2pSwarm, Swarm};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use tracing::{debug, info, warn};
 
-use crate::swarm::SwarmError;
-use crate::{input::ReqSign, Behaviour, DbData, DirectMsgData, MessageData, QueryId, SwarmOutput};
+use crate::swarm::{Settings, SigningMode, SwarmError};
+use crate::{
+    database::Database,
+    input::ReqSign,
+    utils::{check_context, publish, verify},
+    Behaviour, DbData, DirectMsgData, MessageData, QueryId, SwarmOutput,
+};
 
 #[derive(Deserialize, Serialize)]
 pub(crate) enum SigningMessage {
+    Abort(QueryId),
     SignR1(QueryId),
-    SignR2(QueryId, Vec<u8>),
+    SignR2(QueryId, Box<SigningPackage>, bool),
     SignFinal(QueryId, Identifier, round2::SignatureShare),
+    SignResult(QueryId, Signature),
 }
 
 pub(crate) struct Signer {
     pub(crate) data: DbData,
+    last_activity: Instant,
     nonces: Option<round1::SigningNonces>,
     pub(crate) propagation_source: PeerId,
+    returned: bool,
+    rng_seed: Option<u64>,
+    signing_mode: SigningMode,
     signature_db: BTreeMap<Identifier, round2::SignatureShare>,
     signing_package: Option<SigningPackage>,
     query_id: QueryId,
@@ -33,11 +55,16 @@ impl Signer {
         propagation_source: PeerId,
         query_id: QueryId,
         topic: TopicHash,
+        settings: &Settings,
     ) -> Self {
         Self {
             data,
+            last_activity: Instant::now(),
             nonces: None,
             propagation_source,
+            returned: false,
+            rng_seed: settings.rng_seed,
+            signing_mode: settings.signing_mode,
             signature_db: BTreeMap::new(),
             signing_package: None,
             query_id,
@@ -45,6 +72,20 @@ impl Signer {
         }
     }
 
+    pub(crate) fn presigned(
+        data: DbData,
+        propagation_source: PeerId,
+        query_id: QueryId,
+        topic: TopicHash,
+        settings: &Settings,
+        nonces: round1::SigningNonces,
+    ) -> Self {
+        Self {
+            nonces: Some(nonces),
+            ..Self::new(data, propagation_source, query_id, topic, settings)
+        }
+    }
+
     pub(crate) fn sign_r1(&mut self, swarm: &mut Libp2pSwarm<Behaviour>) -> Result<(), SwarmError> {
         let share = self
             .data
@@ -52,8 +93,11 @@ impl Signer {
             .clone()
             .ok_or(SwarmError::DatabaseError)?;
         let share = share.signing_share();
-        let mut rng = rand::rngs::OsRng;
-        let (nonces, commitments) = round1::commit(share, &mut rng);
+        let identifier = self.data.identifier.ok_or(SwarmError::DatabaseError)?;
+        let (nonces, commitments) = match self.rng_seed {
+            Some(seed) => round1::commit(share, &mut seeded_rng(seed, &self.query_id, identifier)),
+            None => round1::commit(share, &mut rand::rngs::OsRng),
+        };
         self.nonces = Some(nonces);
         let _ = swarm.behaviour_mut().req_res.send_request(
             &self.propagation_source,
@@ -99,10 +143,7 @@ impl Signer {
             signature,
         )))
         .map_err(|_| SwarmError::MessageProcessingError)?;
-        let _ = swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topic.clone(), send_message);
+        publish(swarm, self.topic.clone(), send_message);
         Ok(())
     }
 
@@ -115,35 +156,89 @@ impl Signer {
         Ok(self.signature_db.len())
     }
 
+    pub(crate) fn is_complete(&self) -> bool {
+        self.signing_package
+            .as_ref()
+            .is_some_and(|signing_package| {
+                signing_package
+                    .signing_commitments()
+                    .keys()
+                    .all(|identifier| self.signature_db.contains_key(identifier))
+            })
+    }
+
     pub(crate) fn sign_r3(&self) -> Result<Option<Signature>, SwarmError> {
         if self.signing_package.is_none() {
             return Ok(None);
         }
-        let final_signature = frost_ed25519::aggregate(
+        let final_signature = aggregate(
             self.signing_package
                 .as_ref()
                 .ok_or(SwarmError::DatabaseError)?,
             &self.signature_db,
-            &self
-                .data
+            self.data
                 .public_key_package
-                .clone()
+                .as_ref()
                 .ok_or(SwarmError::DatabaseError)?,
-        )
-        .map_err(|_| SwarmError::SigningError)?;
+        )?;
         Ok(Some(final_signature))
     }
 }
 
+// ONLY REACHABLE THROUGH THE TEST-UTIL BUILDER OPTION
+// SHA-512 AND CHACHA20 KEEP THE STREAM STABLE ACROSS RUST AND RAND RELEASES
+pub(crate) fn seeded_rng(seed: u64, query_id: &QueryId, identifier: Identifier) -> ChaCha20Rng {
+    let digest = Sha512::new()
+        .chain_update(seed.to_le_bytes())
+        .chain_update(query_id.as_bytes())
+        .chain_update(identifier.serialize())
+        .finalize();
+    let mut rng_seed = [0u8; 32];
+    rng_seed.copy_from_slice(&digest[..32]);
+    ChaCha20Rng::from_seed(rng_seed)
+}
+
+fn aggregate(
+    signing_package: &SigningPackage,
+    signature_shares: &BTreeMap<Identifier, round2::SignatureShare>,
+    public_key_package: &PublicKeyPackage,
+) -> Result<Signature, SwarmError> {
+    frost_ed25519::aggregate(signing_package, signature_shares, public_key_package).map_err(|e| {
+        match e {
+            FrostError::InvalidSignatureShare { culprit } => {
+                SwarmError::InvalidSignatureShare(culprit)
+            }
+            _ => SwarmError::SigningError,
+        }
+    })
+}
+
 pub(crate) fn handle_signing_msg(
-    database: Arc<DashMap<Vec<u8>, DbData>>,
+    database: Arc<Database>,
     swarm: &mut Libp2pSwarm<Behaviour>,
     signer_db: Arc<DashMap<QueryId, Signer>>,
     message: SigningMessage,
     propagation_source: PeerId,
     topic: TopicHash,
+    settings: &Settings,
 ) -> Result<(), SwarmError> {
+    let query_id = match &message {
+        SigningMessage::Abort(query_id)
+        | SigningMessage::SignR1(query_id)
+        | SigningMessage::SignR2(query_id, ..)
+        | SigningMessage::SignFinal(query_id, ..)
+        | SigningMessage::SignResult(query_id, _) => query_id,
+    };
+    if signer_db
+        .get(query_id)
+        .is_some_and(|signer| signer.topic != topic)
+    {
+        return Err(SwarmError::MessageProcessingError);
+    }
     match message {
+        SigningMessage::Abort(query_id) => {
+            handle_abort_signing(signer_db, propagation_source, query_id)?;
+        }
         SigningMessage::SignR1(query_id) => {
             handle_r1_signing(
                 database,
@@ -152,46 +247,122 @@ pub(crate) fn handle_signing_msg(
                 propagation_source,
                 topic,
                 query_id,
+                settings,
             )?;
         }
-        SigningMessage::SignR2(query_id, data) => {
-            handle_r2_signing(swarm, signer_db, query_id, data)?;
+        SigningMessage::SignR2(query_id, signing_package, contextual) => {
+            handle_r2_signing(swarm, signer_db, query_id, *signing_package, contextual)?;
         }
         SigningMessage::SignFinal(query_id, identifier, signature) => {
             handle_final_signing(swarm, signer_db, query_id, identifier, signature)?;
         }
+        SigningMessage::SignResult(query_id, signature) => {
+            handle_result_signing(signer_db, query_id, signature)?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_result_signing(
+    signer_db: Arc<DashMap<QueryId, Signer>>,
+    query_id: QueryId,
+    signature: Signature,
+) -> Result<(), SwarmError> {
+    let Some(mut signer) = signer_db.get_mut(&query_id) else {
+        return Ok(());
+    };
+    let signing_package = signer
+        .signing_package
+        .as_ref()
+        .ok_or(SwarmError::MessageProcessingError)?;
+    let public_key_package = signer
+        .data
+        .public_key_package
+        .as_ref()
+        .ok_or(SwarmError::DatabaseError)?;
+    verify(
+        public_key_package.verifying_key(),
+        signing_package.message(),
+        &signature,
+    )?;
+    // ANOTHER PARTICIPANT ALREADY AGGREGATED, SO THIS ONE STAYS QUIET
+    signer.returned = true;
+    Ok(())
+}
+
+fn handle_abort_signing(
+    signer_db: Arc<DashMap<QueryId, Signer>>,
+    propagation_source: PeerId,
+    query_id: QueryId,
+) -> Result<(), SwarmError> {
+    // A SESSION THIS NODE NEVER JOINED HAS NOTHING TO ABORT
+    let Some(requester) = signer_db
+        .get(&query_id)
+        .map(|signer| signer.propagation_source)
+    else {
+        return Ok(());
+    };
+    if requester != propagation_source {
+        return Err(SwarmError::InvalidPeer);
     }
+    signer_db.remove(&query_id);
     Ok(())
 }
 
 fn handle_r1_signing(
-    database: Arc<DashMap<Vec<u8>, DbData>>,
+    database: Arc<Database>,
     swarm: &mut Libp2pSwarm<Behaviour>,
     signer_db: Arc<DashMap<QueryId, Signer>>,
     propagation_source: PeerId,
     topic: TopicHash,
     query_id: QueryId,
+    settings: &Settings,
 ) -> Result<(), SwarmError> {
-    let mut signer = Signer::new(
-        database
-            .get(
-                &b64.decode(topic.as_str())
-                    .map_err(|_| SwarmError::MessageProcessingError)?,
-            )
-            .ok_or(SwarmError::DatabaseError)?
-            .clone(),
-        propagation_source,
-        query_id.clone(),
-        topic,
-    );
+    if signer_db.contains_key(&query_id) {
+        return Ok(());
+    }
+    let data = database
+        .get(
+            &b64.decode(topic.as_str())
+                .map_err(|_| SwarmError::MessageProcessingError)?,
+        )
+        .ok_or(SwarmError::DatabaseError)?
+        .clone();
+    // A REQUESTER WITHOUT A SHARE SEES ITS OWN REQUEST BUT HAS NOTHING TO COMMIT
+    if data.key_package.is_none() {
+        return Ok(());
+    }
+    let mut signer = Signer::new(data, propagation_source, query_id.clone(), topic, settings);
+    debug!(query_id = %query_id, requester = %propagation_source, "joining signing");
     signer.sign_r1(swarm)?;
     signer_db.insert(query_id, signer);
     Ok(())
 }
 
+pub(crate) fn sign_request(
+    database: Arc<Database>,
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    signer_db: Arc<DashMap<QueryId, Signer>>,
+    propagation_source: PeerId,
+    query_id: QueryId,
+    public_key: Vec<u8>,
+    settings: &Settings,
+) -> Result<(), SwarmError> {
+    handle_r1_signing(
+        database,
+        swarm,
+        signer_db,
+        propagation_source,
+        TopicHash::from_raw(b64.encode(public_key)),
+        query_id,
+        settings,
+    )
+}
+
 pub(crate) fn signing_package(
     signer_requester_db: &Arc<DashMap<QueryId, ReqSign>>,
     swarm: &mut Swarm<Behaviour>,
+    peer: PeerId,
     query_id: QueryId,
     identifier: Identifier,
     signing_commitments: round1::SigningCommitments,
@@ -202,28 +373,38 @@ pub(crate) fn signing_package(
     let mut sign_requester = signer_requester_db
         .get_mut(&query_id)
         .ok_or(SwarmError::DatabaseError)?;
-    let count = sign_requester.insert_commitments(identifier, signing_commitments)?;
-    if count > sign_requester.signer_config.min_signers as usize {
+    let threshold = match &sign_requester.signers {
+        Some(signers) if !signers.contains(&peer) => return Err(SwarmError::InvalidPeer),
+        Some(signers) => signers.len(),
+        None => sign_requester.signer_config.min_signers as usize + 1,
+    };
+    let count = sign_requester.insert_commitments(peer, identifier, signing_commitments)?;
+    if count >= threshold {
         sign_requester.sign_r2(swarm)?;
     }
     Ok(())
 }
 
-fn handle_r2_signing(
+pub(crate) fn handle_r2_signing(
     swarm: &mut Libp2pSwarm<Behaviour>,
     signer_db: Arc<DashMap<QueryId, Signer>>,
     query_id: QueryId,
-    data: Vec<u8>,
+    signing_package: SigningPackage,
+    contextual: bool,
 ) -> Result<(), SwarmError> {
     if !signer_db.contains_key(&query_id) {
         return Ok(());
     }
+    check_context(signing_package.message(), contextual)?;
     let mut signer = signer_db
         .get_mut(&query_id)
         .ok_or(SwarmError::DatabaseError)?;
-    let signing_package =
-        SigningPackage::deserialize(&data).map_err(|_| SwarmError::MessageProcessingError)?;
+    signer.last_activity = Instant::now();
     signer.sign_r2(swarm, signing_package)?;
+    if signer.is_complete() {
+        let signature = signer.sign_r3()?.ok_or(SwarmError::SigningError)?;
+        return_sign(swarm, query_id, signer, signature)?;
+    }
     Ok(())
 }
 
@@ -240,34 +421,64 @@ fn handle_final_signing(
     let mut signer = signer_db
         .get_mut(&query_id)
         .ok_or(SwarmError::DatabaseError)?;
-    let db_length = signer.insert_r2(identifier, signature)?;
-    if db_length
-        > signer
-            .data
-            .signer_config
-            .clone()
-            .ok_or(SwarmError::DatabaseError)?
-            .min_signers as usize
-    {
+    signer.last_activity = Instant::now();
+    signer.insert_r2(identifier, signature)?;
+    if signer.is_complete() {
         let signature = signer.sign_r3()?.ok_or(SwarmError::SigningError)?;
         return_sign(swarm, query_id, signer, signature)?;
     }
     Ok(())
 }
 
+pub(crate) fn partial_signature(
+    mut output: UnboundedSender<SwarmOutput>,
+    signer_requester_db: &Arc<DashMap<QueryId, ReqSign>>,
+    query_id: QueryId,
+    identifier: Identifier,
+    signature: round2::SignatureShare,
+    topic: TopicHash,
+) -> Result<(), SwarmError> {
+    let mut signer_requester = signer_requester_db
+        .get_mut(&query_id)
+        .ok_or(SwarmError::DatabaseError)?;
+    if topic != TopicHash::from_raw(b64.encode(&signer_requester.pubkey)) {
+        return Err(SwarmError::MessageProcessingError);
+    }
+    if signer_requester.insert_share(identifier, signature) {
+        let _ = output.start_send(SwarmOutput::PartialSignatureReceived(query_id, identifier));
+    }
+    Ok(())
+}
+
+// AGGREGATE LOCALLY TO FIND THE PARTICIPANT THAT SENT A BAD SHARE
+fn aggregation_culprit(signer_requester: &ReqSign) -> Option<Identifier> {
+    let signing_package = signer_requester.signing_package.as_ref()?;
+    match aggregate(
+        signing_package,
+        &signer_requester.shares,
+        &signer_requester.public_key_package,
+    ) {
+        Err(SwarmError::InvalidSignatureShare(culprit)) => Some(culprit),
+        _ => None,
+    }
+}
+
 pub(crate) fn send_signature(
     mut output: UnboundedSender<SwarmOutput>,
     signer_requester_db: &Arc<DashMap<QueryId, ReqSign>>,
+    peer: PeerId,
     query_id: QueryId,
     signature: Signature,
 ) -> Result<(), SwarmError> {
-    let _ = output.start_send(SwarmOutput::Signing(query_id.clone(), signature));
     if !signer_requester_db.contains_key(&query_id) {
         return Ok(());
     }
-    let signer_requester = signer_requester_db
-        .get(&query_id)
+    let mut signer_requester = signer_requester_db
+        .get_mut(&query_id)
         .ok_or(SwarmError::DatabaseError)?;
+    if !signer_requester.is_signer(&peer) {
+        return Err(SwarmError::InvalidPeer);
+    }
     let pubkey = VerifyingKey::deserialize(
         signer_requester
             .pubkey
@@ -276,8 +487,10 @@ pub(crate) fn send_signature(
             .map_err(|_| SwarmError::MessageProcessingError)?,
     )
     .map_err(|_| SwarmError::MessageProcessingError)?;
-    let valid = pubkey.verify(&signer_requester.message, &signature).is_ok();
-    if !valid {
+    let verified = verify(&pubkey, &signer_requester.message, &signature);
+    // A SINGLE SIGNER CANNOT FAIL THE REQUEST WHILE OTHERS MAY STILL RETURN A VALID RESULT
+    if verified.is_err() && !signer_requester.reject_result(peer) {
+        warn!(query_id = %query_id, %peer, "discarding invalid signature");
         return Err(SwarmError::InvalidSignature);
     }
     drop(signer_requester);
@@ -285,19 +498,92 @@ pub(crate) fn send_signature(
         .remove(&query_id)
         .ok_or(SwarmError::DatabaseError)?
         .1;
-    signer_requester.send_response(signature)?;
+    if verified.is_err() {
+        let culprit = aggregation_culprit(&signer_requester);
+        let reason = || culprit.map_or(SwarmError::Aggregation, SwarmError::InvalidSignatureShare);
+        warn!(query_id = %query_id, reason = %reason(), "signing failed");
+        let _ = output.start_send(SwarmOutput::SigningFailed(query_id, reason()));
+        signer_requester.send_response(Err(reason()))?;
+        return Ok(());
+    }
+    info!(query_id = %query_id, "signing complete");
+    let _ = output.start_send(SwarmOutput::Signing(
+        query_id,
+        signature,
+        signer_requester.participants(),
+    ));
+    signer_requester.send_response(Ok(signature))?;
     Ok(())
 }
 
 fn return_sign(
     swarm: &mut Libp2pSwarm<Behaviour>,
     query_id: QueryId,
-    signer: RefMut<QueryId, Signer>,
+    mut signer: RefMut<QueryId, Signer>,
     signature: Signature,
 ) -> Result<(), SwarmError> {
+    if signer.returned {
+        return Ok(());
+    }
+    signer.returned = true;
+    if signer.signing_mode == SigningMode::Distributed {
+        let send_message = bincode::serialize(&MessageData::Signing(SigningMessage::SignResult(
+            query_id.clone(),
+            signature,
+        )))
+        .map_err(|_| SwarmError::MessageProcessingError)?;
+        publish(swarm, signer.topic.clone(), send_message);
+    }
     let _ = swarm.behaviour_mut().req_res.send_request(
         &signer.propagation_source,
         DirectMsgData::ReturnSign(query_id, signature),
     );
     Ok(())
 }
+
+pub(crate) fn expire_signers(
+    round_timeout: Duration,
+    signer_db: &Arc<DashMap<QueryId, Signer>>,
+) -> Result<(), SwarmError> {
+    signer_db.retain(|_, signer| signer.last_activity.elapsed() <= round_timeout);
+    Ok(())
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use frost_ed25519::keys::{generate_with_dealer, IdentifierList, KeyPackage};
+    use rand::RngCore;
+
+    use super::*;
+
+    fn commitment_bytes(seed: u64) -> Vec<u8> {
+        let (shares, _) = generate_with_dealer(
+            3,
+            2,
+            IdentifierList::Default,
+            ChaCha20Rng::seed_from_u64(seed),
+        )
+        .unwrap();
+        let (identifier, share) = shares.into_iter().next().unwrap();
+        let key_package = KeyPackage::try_from(share).unwrap();
+        let query_id = "seeded".to_string();
+        let (_, commitments) = round1::commit(
+            key_package.signing_share(),
+            &mut seeded_rng(seed, &query_id, identifier),
+        );
+        commitments.serialize().unwrap()
+    }
+
+    #[test]
+    fn seeded_commitments_repeat() {
+        assert_eq!(commitment_bytes(7), commitment_bytes(7));
+        assert_ne!(commitment_bytes(7), commitment_bytes(8));
+    }
+
+    #[test]
+    fn seeded_rng_is_pinned() {
+        let identifier = Identifier::try_from(1).unwrap();
+        let mut rng = seeded_rng(7, &"seeded".to_string(), identifier);
+        assert_eq!(rng.next_u64(), 8935033014704063083);
+    }
+}
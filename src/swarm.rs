@@ -1,32 +1,77 @@
-use std::time::Duration;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashSet},
+    hash::{Hash, Hasher},
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use frost_ed25519::{Signature, VerifyingKey};
+use bitflags::bitflags;
+use frost_ed25519::{
+    keys::{KeyPackage, PublicKeyPackage},
+    Identifier, Signature, VerifyingKey,
+};
 use futures::{
     channel::{mpsc, oneshot},
     future::BoxFuture,
-    StreamExt,
+    select,
+    stream::FuturesUnordered,
+    AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt,
 };
+use futures_timer::Delay;
+#[cfg(feature = "test-util")]
+use libp2p::core::transport::MemoryTransport;
 use libp2p::swarm::NetworkBehaviour;
 pub use libp2p::swarm::SwarmEvent;
+#[cfg(feature = "tls")]
+use libp2p::tls;
 use libp2p::{
-    core::upgrade::Version,
-    gossipsub, identify,
+    allow_block_list::{self, AllowedPeers, BlockedPeers},
+    autonat,
+    connection_limits::{self, ConnectionLimits},
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, ListenerId, OrTransport, TransportError},
+        upgrade::Version,
+    },
+    dcutr,
+    gossipsub::{self, IdentTopic, PeerScoreParams, PeerScoreThresholds, Sha256Topic, TopicHash},
+    identify,
     kad::{
-        store::MemoryStore, Behaviour as Kademlia, Config as KademliaConfig,
+        self, store::MemoryStore, Behaviour as Kademlia, Config as KademliaConfig,
         Event as KademliaEvent, Mode,
     },
-    noise,
+    noise, quic, relay,
     request_response::{self, ProtocolSupport},
-    swarm::{Config as Libp2pConfig, StreamProtocol},
-    tcp, yamux, Multiaddr, Swarm as Libp2pSwarm, Transport,
+    swarm::{behaviour::toggle::Toggle, Config as Libp2pConfig, StreamProtocol},
+    tcp, yamux, Multiaddr, PeerId, Swarm as Libp2pSwarm, Transport,
 };
+#[cfg(feature = "websocket")]
+use libp2p::{dns, websocket};
 use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use void::Void;
 
+#[cfg(feature = "metrics")]
 use crate::{
-    start_swarm, utils::PROTOCOL_VERSION, DirectMsgData, Executor, Keypair, QueryId, SignerConfig,
+    bandwidth::PeerBandwidth,
+    metrics::{CountingTransform, Metrics, MetricsSnapshot},
+};
+use crate::{
+    builder::Builder,
+    database::{Database, StorageBackend},
+    direct, start_swarm,
+    utils::{
+        contextualize, load_peer_cache, network_protocol, peerid_from_multiaddress, CONTEXT_TAG,
+    },
+    DirectMsgData, Executor, Keypair, QueryId, SignerConfig, APP_TOPIC_PREFIX, MAX_MESSAGE_SIZE,
 };
 
+pub const PREHASH_LEN: usize = 32;
+pub const GROUP_BUNDLE_VERSION: u8 = 1;
+
 #[derive(Error, Debug)]
 pub enum SwarmError {
     // Task related errors
@@ -36,52 +81,260 @@ pub enum SwarmError {
     SigningError,
     #[error("Produced a signature that is invalid")]
     InvalidSignature,
+    #[error("Query was cancelled")]
+    Cancelled,
+    #[error("Query timed out")]
+    Timeout,
+    #[error("Not enough signers responded to meet the threshold")]
+    ThresholdNotMet(Option<PeerId>),
+    #[error("Signature shares could not be aggregated into a valid signature")]
+    Aggregation,
+    #[error("Participant {0:?} produced an invalid signature share")]
+    InvalidSignatureShare(Identifier),
 
     // Data handling errors
-    #[error("Configuration error")]
+    #[error("Configuration error (if the swarm is not started, call exec() first)")]
     ConfigurationError,
+    #[error("Failed to set up noise encryption")]
+    NoiseSetup(#[source] noise::Error),
+    #[cfg(feature = "tls")]
+    #[error("Failed to set up TLS")]
+    TlsSetup(#[source] libp2p::tls::certificate::GenError),
+    #[cfg(feature = "websocket")]
+    #[error("Failed to set up DNS resolution")]
+    DnsSetup(#[source] std::io::Error),
+    #[error("Invalid gossipsub configuration")]
+    GossipsubConfig(#[source] Box<dyn std::error::Error + Send + Sync>),
     #[error("Message processing error")]
     MessageProcessingError,
+    #[error("Malformed message from {0}")]
+    MalformedMessage(PeerId),
     #[error("Database error")]
     DatabaseError,
+    #[error("Storage error")]
+    StorageError,
+    #[error("Digest must be {PREHASH_LEN} bytes")]
+    InvalidDigest,
+    #[error("Signing context must be at most 255 bytes")]
+    InvalidContext,
+    #[error("Message starts with the reserved signing context tag")]
+    ReservedMessage,
+    #[error("Too many concurrent sessions")]
+    TooManySessions,
+    #[error("Swarm input queue is full")]
+    Busy,
+    #[error("Unsupported or malformed encoding")]
+    InvalidEncoding,
+    #[error("Swarm task panicked")]
+    TaskPanicked,
+    #[error("No group is known for this verifying key")]
+    UnknownKey,
 
     // Network related errors
     #[error("Invalid peer responded")]
     InvalidPeer,
+    #[error("Multiaddr does not contain a peer id")]
+    InvalidMultiaddr,
+    #[error("Peer exceeded the inbound request rate limit")]
+    RateLimited,
+    #[error("Failed to dial peer")]
+    DialFailure,
+    #[error("Failed to listen on {0}")]
+    ListenFailed(Multiaddr, #[source] TransportError<std::io::Error>),
+    #[error("A selected participant disconnected")]
+    ParticipantDropped,
+    #[error("Not enough peers are known to fill every seat")]
+    InsufficientPeers,
+}
+
+impl SwarmError {
+    // FAILURES THAT A FRESH ATTEMPT WITH OTHER PEERS MAY AVOID
+    pub(crate) fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            SwarmError::Timeout
+                | SwarmError::ThresholdNotMet(_)
+                | SwarmError::ParticipantDropped
+                | SwarmError::DialFailure
+        )
+    }
+
+    // ERRORS THAT THE AUTHOR OF A GOSSIP MESSAGE IS TO BLAME FOR. A RATE LIMITED PEER MAY
+    // JUST BE BUSY, SO ITS REQUESTS ARE DROPPED WITHOUT A PENALTY
+    pub(crate) fn is_misbehaviour(&self) -> bool {
+        matches!(
+            self,
+            SwarmError::MalformedMessage(_)
+                | SwarmError::InvalidSignature
+                | SwarmError::InvalidSignatureShare(_)
+        )
+    }
 }
 
 #[derive(Debug)]
 pub enum SwarmInput {
     AddPeer(Multiaddr),
-    Generate(QueryId, SignerConfig, oneshot::Sender<VerifyingKey>),
-    Sign(QueryId, oneshot::Sender<Signature>, Vec<u8>, Vec<u8>),
+    RemovePeer(PeerId),
+    Dial(Multiaddr, oneshot::Sender<Result<PeerId, SwarmError>>),
+    Subscribe(String),
+    Unsubscribe(String),
+    Publish(String, Vec<u8>),
+    Generate(
+        QueryId,
+        SignerConfig,
+        oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
+    ),
+    CanGenerate(u16, oneshot::Sender<Result<Vec<PeerId>, SwarmError>>),
+    WaitForPeers(usize, oneshot::Sender<()>),
+    Sign(
+        QueryId,
+        oneshot::Sender<Result<Signature, SwarmError>>,
+        Vec<u8>,
+        Vec<u8>,
+        Option<Vec<PeerId>>,
+        bool,
+    ),
+    Presign(
+        QueryId,
+        Vec<u8>,
+        u16,
+        oneshot::Sender<Result<usize, SwarmError>>,
+    ),
+    Reshare(
+        QueryId,
+        Vec<u8>,
+        SignerConfig,
+        Vec<Multiaddr>,
+        oneshot::Sender<Result<PublicKeyPackage, SwarmError>>,
+    ),
+    Cancel(QueryId),
+    Listeners(oneshot::Sender<Vec<Multiaddr>>),
+    ActiveSessions(oneshot::Sender<usize>),
+    KeyPackage(Vec<u8>, oneshot::Sender<Option<KeyPackage>>),
+    PublicKeyPackage(Vec<u8>, oneshot::Sender<Option<PublicKeyPackage>>),
+    SignerConfig(Vec<u8>, oneshot::Sender<Option<SignerConfig>>),
+    ListKeys(oneshot::Sender<Vec<VerifyingKey>>),
+    ParticipantMap(Vec<u8>, oneshot::Sender<Vec<(PeerId, Identifier)>>),
+    ExportGroup(Vec<u8>, oneshot::Sender<Option<GroupBundle>>),
+    ImportGroup(Box<GroupBundle>, oneshot::Sender<Result<(), SwarmError>>),
+    GroupStatus(Vec<u8>, oneshot::Sender<Option<GroupStatus>>),
+    Health(oneshot::Sender<HealthSnapshot>),
+    SetAccepting(bool),
+    Shutdown(oneshot::Sender<()>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupStatus {
+    pub total: usize,
+    pub online: usize,
+    pub threshold: u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HealthSnapshot {
+    pub alive: bool,
+    pub accepting: bool,
+    pub connected_peers: usize,
+    pub pending_queries: usize,
+    pub held_keys: usize,
+    pub subscribed_topics: usize,
+    pub listeners: Vec<Multiaddr>,
+}
+
+/// The network as seen locally: connected peers with the addresses Kademlia knows for
+/// them, and the number of entries in each non-empty bucket, keyed by bucket index.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TopologySnapshot {
+    pub connected_peers: Vec<(PeerId, Vec<Multiaddr>)>,
+    pub buckets: Vec<(u32, usize)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Round {
+    Discovery,
+    Round1,
+    Round2,
 }
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum SwarmOutput {
     Error(SwarmError),
     Generation(QueryId, VerifyingKey),
-    Signing(QueryId, Signature),
+    GenerationProgress(QueryId, Round, usize, usize),
+    JoinedGroup(VerifyingKey, SignerConfig, BTreeMap<Identifier, PeerId>),
+    PeerDiscovered(PeerId, Vec<Multiaddr>),
+    PeerExpired(PeerId),
+    ListenFailed(Multiaddr, TransportError<std::io::Error>),
+    BootstrapComplete,
+    PartialSignatureReceived(QueryId, Identifier),
+    Signing(QueryId, Signature, BTreeMap<Identifier, PeerId>),
+    SigningFailed(QueryId, SwarmError),
+    Message {
+        topic: String,
+        source: Option<PeerId>,
+        data: Vec<u8>,
+    },
     SwarmEvents(SwarmEvent<BehaviourEvent>),
+    TopologySnapshot(TopologySnapshot),
+    NatStatus(autonat::NatStatus),
 }
 
+#[cfg(feature = "metrics")]
+pub(crate) type Gossipsub = gossipsub::Behaviour<CountingTransform>;
+#[cfg(not(feature = "metrics"))]
+pub(crate) type Gossipsub = gossipsub::Behaviour;
+
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm = "BehaviourEvent")]
 pub(crate) struct Behaviour {
-    pub(crate) gossipsub: gossipsub::Behaviour,
+    pub(crate) allowed_peers: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
+    pub(crate) autonat: autonat::Behaviour,
+    pub(crate) blocked_peers: allow_block_list::Behaviour<BlockedPeers>,
+    pub(crate) connection_limits: connection_limits::Behaviour,
+    pub(crate) dcutr: Toggle<dcutr::Behaviour>,
+    pub(crate) gossipsub: Gossipsub,
     pub(crate) identify: identify::Behaviour,
     pub(crate) kad: Kademlia<MemoryStore>,
-    pub(crate) req_res: request_response::cbor::Behaviour<DirectMsgData, Vec<u8>>,
+    pub(crate) relay_client: Toggle<relay::client::Behaviour>,
+    pub(crate) req_res: direct::Behaviour,
 }
 
 #[derive(Debug)]
 pub enum BehaviourEvent {
+    Autonat(autonat::Event),
+    Dcutr(dcutr::Event),
     Gossipsub(gossipsub::Event),
     Identify(identify::Event),
     Kademlia(KademliaEvent),
+    Relay(relay::client::Event),
     RequestResponse(request_response::Event<DirectMsgData, Vec<u8>>),
 }
 
+impl From<Void> for BehaviourEvent {
+    fn from(event: Void) -> Self {
+        void::unreachable(event)
+    }
+}
+
+impl From<autonat::Event> for BehaviourEvent {
+    fn from(event: autonat::Event) -> Self {
+        BehaviourEvent::Autonat(event)
+    }
+}
+
+impl From<dcutr::Event> for BehaviourEvent {
+    fn from(event: dcutr::Event) -> Self {
+        BehaviourEvent::Dcutr(event)
+    }
+}
+
+impl From<relay::client::Event> for BehaviourEvent {
+    fn from(event: relay::client::Event) -> Self {
+        BehaviourEvent::Relay(event)
+    }
+}
+
 impl From<gossipsub::Event> for BehaviourEvent {
     fn from(event: gossipsub::Event) -> Self {
         BehaviourEvent::Gossipsub(event)
@@ -106,139 +359,1329 @@ impl From<request_response::Event<DirectMsgData, Vec<u8>>> for BehaviourEvent {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct TimeoutConfig {
+    /// Bounds how long a request gathers participants, or commitments when signing or presigning.
+    pub discovery: Duration,
+    /// Bounds how long a participant keeps a session that has gone quiet.
+    pub round: Duration,
+    /// Bounds a whole request, counted from when it starts.
+    pub overall: Duration,
+    /// Interval between pings to signers that still owe a share. `None` disables them.
+    pub liveness: Option<Duration>,
+    /// Bounds how long a presigning session and the nonces it produces stay usable.
+    pub presign: Duration,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            discovery: Duration::from_secs(30),
+            round: Duration::from_secs(120),
+            overall: Duration::from_secs(120),
+            liveness: None,
+            presign: Duration::from_secs(600),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GroupBundle {
+    pub key_package: KeyPackage,
+    pub public_key_package: PublicKeyPackage,
+    pub signer_config: SignerConfig,
+    pub participants: BTreeMap<Identifier, PeerId>,
+}
+
+impl GroupBundle {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SwarmError> {
+        let mut bytes = vec![GROUP_BUNDLE_VERSION];
+        bytes.extend(bincode::serialize(self).map_err(|_| SwarmError::StorageError)?);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SwarmError> {
+        match bytes.split_first() {
+            Some((&GROUP_BUNDLE_VERSION, bundle)) => {
+                bincode::deserialize(bundle).map_err(|_| SwarmError::StorageError)
+            }
+            _ => Err(SwarmError::StorageError),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionLimitConfig {
+    pub max_established: u32,
+    pub max_pending: u32,
+}
+
+impl Default for ConnectionLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_established: 512,
+            max_pending: 128,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RequestResponseConfig {
+    pub request_timeout: Duration,
+    pub max_concurrent_streams: usize,
+}
+
+impl Default for RequestResponseConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(30),
+            max_concurrent_streams: 100,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            burst: 32,
+            per_second: 4.0,
+        }
+    }
+}
+
+impl RateLimit {
+    pub(crate) fn is_valid(&self) -> bool {
+        self.burst > 0 && self.per_second.is_finite() && self.per_second > 0.0
+    }
+}
+
+/// Gossipsub peer scoring. Each gossip message that fails validation lowers the author's
+/// application score by `penalty`, and every tick the score is multiplied by `decay`.
+#[derive(Clone, Debug)]
+pub struct PeerScoring {
+    pub params: PeerScoreParams,
+    pub thresholds: PeerScoreThresholds,
+    pub penalty: f64,
+    pub decay: f64,
+}
+
+impl Default for PeerScoring {
+    fn default() -> Self {
+        Self {
+            // PARTICIPANTS OFTEN SHARE A HOST, SO DON'T PENALIZE COLOCATION
+            params: PeerScoreParams {
+                ip_colocation_factor_weight: 0.0,
+                ..Default::default()
+            },
+            thresholds: PeerScoreThresholds::default(),
+            penalty: 1.0,
+            decay: 0.99,
+        }
+    }
+}
+
+/// Runs on every received gossip message before it is handled or forwarded. Anything but
+/// `Accept` drops the message, and `Reject` also lowers the sender's score.
+pub type MessageValidator =
+    Arc<dyn Fn(&gossipsub::Message) -> gossipsub::MessageAcceptance + Send + Sync>;
+
+type MultiSignFuture<'a> = BoxFuture<'a, Result<Vec<(VerifyingKey, Signature)>, SwarmError>>;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MultiSignMode {
+    #[default]
+    AllOrNothing,
+    BestEffort,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum TransportKind {
+    #[cfg(feature = "test-util")]
+    Memory,
+    #[default]
+    Tcp,
+    Quic,
+    Both,
+}
+
+bitflags! {
+    /// Categories of `SwarmEvent` forwarded as `SwarmOutput::SwarmEvents`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct EventFilter: u8 {
+        const CONNECTION = 1;
+        const LISTENER = 1 << 1;
+        const EXTERNAL_ADDRESS = 1 << 2;
+    }
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl EventFilter {
+    pub(crate) fn allows(&self, event: &SwarmEvent<BehaviourEvent>) -> bool {
+        let category = match event {
+            SwarmEvent::ConnectionEstablished { .. }
+            | SwarmEvent::ConnectionClosed { .. }
+            | SwarmEvent::IncomingConnection { .. }
+            | SwarmEvent::IncomingConnectionError { .. }
+            | SwarmEvent::OutgoingConnectionError { .. }
+            | SwarmEvent::Dialing { .. } => Self::CONNECTION,
+            SwarmEvent::NewListenAddr { .. }
+            | SwarmEvent::ExpiredListenAddr { .. }
+            | SwarmEvent::ListenerClosed { .. }
+            | SwarmEvent::ListenerError { .. } => Self::LISTENER,
+            SwarmEvent::NewExternalAddrCandidate { .. }
+            | SwarmEvent::ExternalAddrConfirmed { .. }
+            | SwarmEvent::ExternalAddrExpired { .. } => Self::EXTERNAL_ADDRESS,
+            _ => return true,
+        };
+        self.intersects(category)
+    }
+}
+
+/// How generation session ids are turned into gossipsub topics. `Sha256` keeps the id itself
+/// off the wire.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TopicHashing {
+    #[default]
+    Identity,
+    Sha256,
+}
+
+impl TopicHashing {
+    pub(crate) fn hash(&self, name: &str) -> TopicHash {
+        match self {
+            TopicHashing::Identity => IdentTopic::new(name).hash(),
+            TopicHashing::Sha256 => Sha256Topic::new(name).hash(),
+        }
+    }
+
+    pub(crate) fn subscribe(&self, gossipsub: &mut Gossipsub, name: &str) {
+        let _ = match self {
+            TopicHashing::Identity => gossipsub.subscribe(&IdentTopic::new(name)),
+            TopicHashing::Sha256 => gossipsub.subscribe(&Sha256Topic::new(name)),
+        };
+    }
+
+    pub(crate) fn unsubscribe(&self, gossipsub: &mut Gossipsub, name: &str) {
+        let _ = match self {
+            TopicHashing::Identity => gossipsub.unsubscribe(&IdentTopic::new(name)),
+            TopicHashing::Sha256 => gossipsub.unsubscribe(&Sha256Topic::new(name)),
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SigningMode {
+    #[default]
+    Coordinator,
+    Distributed,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Security {
+    #[default]
+    Noise,
+    #[cfg(feature = "tls")]
+    Tls,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Authenticity {
+    #[default]
+    Signed,
+    Author,
+    /// Messages carry no author, so only application topics work: generation, signing,
+    /// presigning and resharing are refused with `ConfigurationError`.
+    Anonymous,
+}
+
+#[derive(Clone)]
+pub(crate) struct Settings {
+    pub(crate) authenticity: Authenticity,
+    pub(crate) max_concurrent_sessions: Option<usize>,
+    pub(crate) generate_retries: u32,
+    pub(crate) rate_limit: RateLimit,
+    pub(crate) peer_scoring: Option<PeerScoring>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) timeouts: TimeoutConfig,
+    pub(crate) peer_cache: Option<PathBuf>,
+    pub(crate) bootstrap_query: Option<kad::QueryId>,
+    pub(crate) allowed_peers: Option<HashSet<PeerId>>,
+    pub(crate) denied_peers: HashSet<PeerId>,
+    pub(crate) rng_seed: Option<u64>,
+    pub(crate) signing_mode: SigningMode,
+    pub(crate) topic_hashing: TopicHashing,
+    pub(crate) event_filter: EventFilter,
+    pub(crate) topology_interval: Option<Duration>,
+    pub(crate) message_validator: Option<MessageValidator>,
+    pub(crate) protocol_version: String,
+}
+
+impl Settings {
+    pub(crate) fn permits(&self, peer: &PeerId) -> bool {
+        !self.denied_peers.contains(peer)
+            && self
+                .allowed_peers
+                .as_ref()
+                .is_none_or(|allowed_peers| allowed_peers.contains(peer))
+    }
+}
+
 pub struct Swarm {
-    pub input_tx: Option<mpsc::UnboundedSender<SwarmInput>>,
-    pub output_rx: Option<mpsc::UnboundedReceiver<SwarmOutput>>,
+    pub(crate) input_tx: Option<mpsc::Sender<SwarmInput>>,
+    pub(crate) output_rx: Option<mpsc::UnboundedReceiver<SwarmOutput>>,
     pub key: Keypair,
     pub addresses: Vec<Multiaddr>,
     pub executor: fn(BoxFuture<'static, ()>),
+    pub storage: StorageBackend,
+    pub timeouts: TimeoutConfig,
+    pub transport: TransportKind,
+    pub authenticity: Authenticity,
+    pub security: Security,
+    pub signing_mode: SigningMode,
+    pub topic_hashing: TopicHashing,
+    pub event_filter: EventFilter,
+    pub network_id: Option<String>,
+    pub max_concurrent_sessions: Option<usize>,
+    pub rate_limit: RateLimit,
+    pub peer_scoring: Option<PeerScoring>,
+    pub connection_limits: ConnectionLimitConfig,
+    pub request_response: RequestResponseConfig,
+    pub gossipsub_config: Option<gossipsub::Config>,
+    pub message_validator: Option<MessageValidator>,
+    pub relay: bool,
+    pub peer_cache: Option<PathBuf>,
+    pub bootstrap_peers: Vec<Multiaddr>,
+    pub topology_interval: Option<Duration>,
+    pub input_capacity: usize,
+    pub generate_retries: u32,
+    pub allowed_peers: Option<HashSet<PeerId>>,
+    pub denied_peers: HashSet<PeerId>,
+    pub idle_connection_timeout: Duration,
+    #[cfg(feature = "test-util")]
+    pub rng_seed: Option<u64>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Arc<Metrics>,
 }
 
 impl Swarm {
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
     pub fn exec(&mut self) -> Result<(), SwarmError> {
-        let (input_tx, input_rx) = mpsc::unbounded::<SwarmInput>();
-        let (output_tx, output_rx) = mpsc::unbounded::<SwarmOutput>();
+        if !self.rate_limit.is_valid() {
+            return Err(SwarmError::ConfigurationError);
+        }
+        let (input_tx, input_rx) = mpsc::channel::<SwarmInput>(self.input_capacity);
+        let (mut output_tx, output_rx) = mpsc::unbounded::<SwarmOutput>();
         self.input_tx = Some(input_tx);
         self.output_rx = Some(output_rx);
-        let swarm = create_libp2p_swarm(self)?;
+        let database = Database::new(self.storage.clone())?;
+        let mut settings = Settings {
+            authenticity: self.authenticity,
+            max_concurrent_sessions: self.max_concurrent_sessions,
+            generate_retries: self.generate_retries,
+            rate_limit: self.rate_limit.clone(),
+            peer_scoring: self.peer_scoring.clone(),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+            timeouts: self.timeouts.clone(),
+            peer_cache: self.peer_cache.clone(),
+            bootstrap_query: None,
+            allowed_peers: self.allowed_peers.clone(),
+            denied_peers: self.denied_peers.clone(),
+            #[cfg(feature = "test-util")]
+            rng_seed: self.rng_seed,
+            #[cfg(not(feature = "test-util"))]
+            rng_seed: None,
+            signing_mode: self.signing_mode,
+            topic_hashing: self.topic_hashing,
+            event_filter: self.event_filter,
+            topology_interval: self.topology_interval,
+            message_validator: self.message_validator.clone(),
+            protocol_version: network_protocol(self.network_id.as_deref()),
+        };
+        let (swarm, listeners, listen_failures, bootstrap_query) = create_libp2p_swarm(self)?;
+        settings.bootstrap_query = bootstrap_query;
+        for (address, error) in listen_failures {
+            let _ = output_tx.start_send(SwarmOutput::ListenFailed(address, error));
+        }
+        let mut panic_tx = output_tx.clone();
         self.executor.exec(Box::pin(async move {
-            let _ = start_swarm(input_rx, output_tx, swarm).await;
+            let swarm_loop = start_swarm(input_rx, output_tx, swarm, listeners, database, settings);
+            // SURFACE A PANIC BEFORE THE OUTPUT CHANNEL CLOSES
+            if AssertUnwindSafe(swarm_loop).catch_unwind().await.is_err() {
+                let _ = panic_tx.start_send(SwarmOutput::Error(SwarmError::TaskPanicked));
+            }
         }));
         Ok(())
     }
 
+    fn send_input(&mut self, input: SwarmInput) -> Result<(), SwarmError> {
+        let input_tx = self
+            .input_tx
+            .as_mut()
+            .ok_or(SwarmError::ConfigurationError)?;
+        input_tx.try_send(input).map_err(|e| {
+            if e.is_full() {
+                SwarmError::Busy
+            } else {
+                SwarmError::MessageProcessingError
+            }
+        })
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Total bytes received and sent over all connections since `exec`.
+    #[cfg(feature = "metrics")]
+    pub fn bandwidth(&self) -> (u64, u64) {
+        let total = self.metrics.bandwidth.total();
+        (total.inbound, total.outbound)
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn peer_bandwidth(&self) -> Vec<(PeerId, PeerBandwidth)> {
+        self.metrics.bandwidth.peers()
+    }
+
+    /// Before `exec`, yields `SwarmOutput::Error(SwarmError::ConfigurationError)` once and
+    /// then `None`, like a swarm that stopped right away.
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> BoxFuture<'_, Option<SwarmOutput>> {
-        Box::pin(self.output_rx.as_mut().unwrap().next())
+        let output_rx = self.output_rx.get_or_insert_with(unstarted_output);
+        Box::pin(output_rx.next())
+    }
+
+    /// Resolves to `None` once the swarm task has stopped, or `Some(Err(SwarmError::Timeout))`
+    /// if it is alive but produced nothing within `timeout`. Before `exec`, behaves like `next`.
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> BoxFuture<'_, Option<Result<SwarmOutput, SwarmError>>> {
+        let output_rx = self.output_rx.get_or_insert_with(unstarted_output);
+        Box::pin(async move {
+            select! {
+                output = output_rx.next().fuse() => output.map(Ok),
+                _ = Delay::new(timeout).fuse() => Some(Err(SwarmError::Timeout)),
+            }
+        })
     }
 
     pub fn add_peer(&mut self, multiaddr: Multiaddr) -> Result<(), SwarmError> {
+        peerid_from_multiaddress(&multiaddr).ok_or(SwarmError::InvalidMultiaddr)?;
         let send_message = SwarmInput::AddPeer(multiaddr);
-        let _ = self.input_tx.as_mut().unwrap().start_send(send_message);
-        Ok(())
+        self.send_input(send_message)
+    }
+
+    pub fn dial(&mut self, multiaddr: Multiaddr) -> BoxFuture<'_, Result<PeerId, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Result<PeerId, SwarmError>>();
+        let send_message = SwarmInput::Dial(multiaddr, tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)?
+        })
     }
 
+    pub fn subscribe(&mut self, topic: String) -> Result<(), SwarmError> {
+        let send_message = SwarmInput::Subscribe(topic);
+        self.send_input(send_message)
+    }
+
+    pub fn unsubscribe(&mut self, topic: String) -> Result<(), SwarmError> {
+        let send_message = SwarmInput::Unsubscribe(topic);
+        self.send_input(send_message)
+    }
+
+    pub fn publish(&mut self, topic: String, data: Vec<u8>) -> Result<(), SwarmError> {
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(SwarmError::MessageProcessingError);
+        }
+        let send_message = SwarmInput::Publish(topic, data);
+        self.send_input(send_message)
+    }
+
+    pub fn remove_peer(&mut self, peer: PeerId) -> Result<(), SwarmError> {
+        let send_message = SwarmInput::RemovePeer(peer);
+        self.send_input(send_message)
+    }
+
+    /// Generates a key shared by `total_peers` participants: the local node and
+    /// `total_peers - 1` peers picked from the routing table. The initiator holds a share like
+    /// every other participant. Fails with [`SwarmError::InsufficientPeers`] when the routing
+    /// table is too small.
     pub fn generate(
         &mut self,
         min_threshold: u16,
         total_peers: u16,
     ) -> (QueryId, BoxFuture<'_, Result<VerifyingKey, SwarmError>>) {
-        let (tx, rx) = oneshot::channel::<VerifyingKey>();
-        let query_id = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect::<String>();
-        let send_message = SwarmInput::Generate(
+        let query_id = new_query_id();
+        (
             query_id.clone(),
-            SignerConfig {
-                max_signers: total_peers,
-                min_signers: min_threshold,
-            },
+            self.generate_with_id(query_id, min_threshold, total_peers),
+        )
+    }
+
+    pub fn generate_with_id(
+        &mut self,
+        query_id: QueryId,
+        min_threshold: u16,
+        total_peers: u16,
+    ) -> BoxFuture<'_, Result<VerifyingKey, SwarmError>> {
+        let response = self.start_generate(query_id, min_threshold, total_peers);
+        Box::pin(async move {
+            let pubkey_package = response.await?;
+            Ok(*pubkey_package.verifying_key())
+        })
+    }
+
+    /// Checks discovery without starting a generation, counting the local node as one of the
+    /// `total_peers` participants. Peers advertising another protocol version are evicted from
+    /// the routing table on identify, so only connected, compatible peers are returned.
+    pub fn can_generate(
+        &mut self,
+        total_peers: u16,
+    ) -> BoxFuture<'_, Result<Vec<PeerId>, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<PeerId>, SwarmError>>();
+        let send_message = SwarmInput::CanGenerate(total_peers, tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)?
+        })
+    }
+
+    pub fn wait_for_peers(
+        &mut self,
+        count: usize,
+        timeout: Duration,
+    ) -> BoxFuture<'_, Result<(), SwarmError>> {
+        let (tx, rx) = oneshot::channel::<()>();
+        let send_message = SwarmInput::WaitForPeers(count, tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            select! {
+                response = rx.fuse() => response.map_err(|_| SwarmError::MessageProcessingError),
+                _ = Delay::new(timeout).fuse() => Err(SwarmError::Timeout),
+            }
+        })
+    }
+
+    pub fn generate_with_packages(
+        &mut self,
+        min_threshold: u16,
+        total_peers: u16,
+    ) -> (QueryId, BoxFuture<'_, Result<PublicKeyPackage, SwarmError>>) {
+        let query_id = new_query_id();
+        (
+            query_id.clone(),
+            self.start_generate(query_id, min_threshold, total_peers),
+        )
+    }
+
+    fn start_generate(
+        &mut self,
+        query_id: QueryId,
+        min_threshold: u16,
+        total_peers: u16,
+    ) -> BoxFuture<'_, Result<PublicKeyPackage, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Result<PublicKeyPackage, SwarmError>>();
+        let signer_config = SignerConfig::new(min_threshold, total_peers);
+        if !is_valid_query_id(&query_id) || !signer_config.is_valid() {
+            return Box::pin(async { Err(SwarmError::ConfigurationError) });
+        }
+        let send_message = SwarmInput::Generate(query_id, signer_config, tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)?
+        })
+    }
+
+    pub fn sign(
+        &mut self,
+        pubkey: VerifyingKey,
+        message: Vec<u8>,
+    ) -> (QueryId, BoxFuture<'_, Result<Signature, SwarmError>>) {
+        let query_id = new_query_id();
+        (
+            query_id.clone(),
+            self.start_sign(query_id, pubkey, message, None),
+        )
+    }
+
+    pub fn sign_with_id(
+        &mut self,
+        query_id: QueryId,
+        pubkey: VerifyingKey,
+        message: Vec<u8>,
+    ) -> BoxFuture<'_, Result<Signature, SwarmError>> {
+        self.start_sign(query_id, pubkey, message, None)
+    }
+
+    pub fn sign_with(
+        &mut self,
+        pubkey: VerifyingKey,
+        message: Vec<u8>,
+        signers: Vec<PeerId>,
+    ) -> (QueryId, BoxFuture<'_, Result<Signature, SwarmError>>) {
+        let query_id = new_query_id();
+        (
+            query_id.clone(),
+            self.start_sign(query_id, pubkey, message, Some(signers)),
+        )
+    }
+
+    /// Signatures made under different contexts over the same message are distinct and only
+    /// verify with `verify_with_context` and the same context. They are not Ed25519ctx
+    /// signatures.
+    pub fn sign_with_context(
+        &mut self,
+        pubkey: VerifyingKey,
+        context: Vec<u8>,
+        message: Vec<u8>,
+    ) -> (QueryId, BoxFuture<'_, Result<Signature, SwarmError>>) {
+        let query_id = new_query_id();
+        let message = match contextualize(&context, &message) {
+            Ok(message) => message,
+            Err(e) => return (query_id, Box::pin(async { Err(e) })),
+        };
+        (
+            query_id.clone(),
+            self.send_sign(query_id, pubkey, message, None, true),
+        )
+    }
+
+    pub fn sign_multi(
+        &mut self,
+        keys: Vec<VerifyingKey>,
+        message: Vec<u8>,
+    ) -> (QueryId, MultiSignFuture<'_>) {
+        self.sign_multi_with_mode(keys, message, MultiSignMode::default())
+    }
+
+    /// Runs one signing session per key, with query ids `{query_id}-{index}`. In
+    /// `AllOrNothing` mode the first failure cancels the remaining sessions; in `BestEffort`
+    /// mode the signatures that succeeded are returned, and an error only if none did.
+    pub fn sign_multi_with_mode(
+        &mut self,
+        keys: Vec<VerifyingKey>,
+        message: Vec<u8>,
+        mode: MultiSignMode,
+    ) -> (QueryId, MultiSignFuture<'_>) {
+        let query_id = new_query_id();
+        if keys.is_empty() {
+            return (
+                query_id,
+                Box::pin(async { Err(SwarmError::ConfigurationError) }),
+            );
+        }
+        if message.starts_with(CONTEXT_TAG) {
+            return (
+                query_id,
+                Box::pin(async { Err(SwarmError::ReservedMessage) }),
+            );
+        }
+        let mut sessions = FuturesUnordered::new();
+        for (index, pubkey) in keys.into_iter().enumerate() {
+            let session_id = format!("{query_id}-{index}");
+            let (tx, rx) = oneshot::channel::<Result<Signature, SwarmError>>();
+            let send_message = SwarmInput::Sign(
+                session_id.clone(),
+                tx,
+                pubkey.serialize().to_vec(),
+                message.clone(),
+                None,
+                false,
+            );
+            let sent = self.send_input(send_message);
+            sessions.push(async move {
+                let response = match sent {
+                    Ok(()) => rx.await.map_err(|_| SwarmError::MessageProcessingError),
+                    Err(e) => Err(e),
+                };
+                (
+                    index,
+                    session_id,
+                    pubkey,
+                    response.and_then(|result| result),
+                )
+            });
+        }
+        let mut input_tx = self.input_tx.clone();
+        let batch_id = query_id.clone();
+        let response = Box::pin(async move {
+            let mut outstanding = (0..sessions.len())
+                .map(|index| format!("{query_id}-{index}"))
+                .collect::<BTreeSet<_>>();
+            let mut signatures = Vec::new();
+            let mut failure = None;
+            while let Some((index, session_id, pubkey, result)) = sessions.next().await {
+                outstanding.remove(&session_id);
+                match result {
+                    Ok(signature) => signatures.push((index, pubkey, signature)),
+                    Err(e) if mode == MultiSignMode::AllOrNothing => {
+                        for session_id in outstanding {
+                            if let Some(input_tx) = input_tx.as_mut() {
+                                let _ = input_tx.try_send(SwarmInput::Cancel(session_id));
+                            }
+                        }
+                        return Err(e);
+                    }
+                    Err(e) => failure = Some(e),
+                }
+            }
+            if let (true, Some(e)) = (signatures.is_empty(), failure) {
+                return Err(e);
+            }
+            signatures.sort_by_key(|(index, ..)| *index);
+            Ok(signatures
+                .into_iter()
+                .map(|(_, pubkey, signature)| (pubkey, signature))
+                .collect())
+        });
+        (batch_id, response)
+    }
+
+    /// Signs a digest computed by the caller instead of the raw message.
+    ///
+    /// FROST-Ed25519 always derives its challenge as `SHA-512(R || A || M)`, so the digest is
+    /// signed as the message `M`; no hashing step is skipped. Verifiers must check the signature
+    /// against the digest bytes, not the original data, and the result is not an Ed25519ph
+    /// signature. The binding between digest and data is only as strong as the hash the caller
+    /// used, so use a collision-resistant 32-byte hash such as SHA-256 or BLAKE2s, and never
+    /// sign a digest you have not computed yourself.
+    pub fn sign_prehashed(
+        &mut self,
+        pubkey: VerifyingKey,
+        digest: Vec<u8>,
+    ) -> (QueryId, BoxFuture<'_, Result<Signature, SwarmError>>) {
+        let query_id = new_query_id();
+        if digest.len() != PREHASH_LEN {
+            return (query_id, Box::pin(async { Err(SwarmError::InvalidDigest) }));
+        }
+        (
+            query_id.clone(),
+            self.start_sign(query_id, pubkey, digest, None),
+        )
+    }
+
+    /// Like `sign_prehashed`, for callers hashing with SHA-512. The digest is signed as the
+    /// message, so verify against the digest: Ed25519 hashes `R || A || M` together, which cannot
+    /// be computed from a digest of `M` alone.
+    pub fn sign_digest(
+        &mut self,
+        pubkey: VerifyingKey,
+        digest: [u8; 64],
+    ) -> (QueryId, BoxFuture<'_, Result<Signature, SwarmError>>) {
+        let query_id = new_query_id();
+        (
+            query_id.clone(),
+            self.start_sign(query_id, pubkey, digest.to_vec(), None),
+        )
+    }
+
+    // A PLAIN MESSAGE MUST NOT PASS FOR THE ENCODING OF A CONTEXT SIGNATURE
+    fn start_sign(
+        &mut self,
+        query_id: QueryId,
+        pubkey: VerifyingKey,
+        message: Vec<u8>,
+        signers: Option<Vec<PeerId>>,
+    ) -> BoxFuture<'_, Result<Signature, SwarmError>> {
+        if message.starts_with(CONTEXT_TAG) {
+            return Box::pin(async { Err(SwarmError::ReservedMessage) });
+        }
+        self.send_sign(query_id, pubkey, message, signers, false)
+    }
+
+    fn send_sign(
+        &mut self,
+        query_id: QueryId,
+        pubkey: VerifyingKey,
+        message: Vec<u8>,
+        signers: Option<Vec<PeerId>>,
+        contextual: bool,
+    ) -> BoxFuture<'_, Result<Signature, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Result<Signature, SwarmError>>();
+        if !is_valid_query_id(&query_id) {
+            return Box::pin(async { Err(SwarmError::ConfigurationError) });
+        }
+        let send_message = SwarmInput::Sign(
+            query_id,
             tx,
+            pubkey.serialize().to_vec(),
+            message,
+            signers,
+            contextual,
         );
-        let _ = self.input_tx.as_mut().unwrap().start_send(send_message);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)?
+        })
+    }
+
+    /// Has the group pre-generate `count` nonce commitments, so up to `count` later `sign`
+    /// calls on this key skip the commitment round. Every nonce signs at most once: a
+    /// participant deletes it before signing, and unused ones expire after
+    /// `TimeoutConfig::presign`. Nonces live in memory only and never touch storage, so a
+    /// restart discards them rather than risking reuse. Resolves to the number of sessions
+    /// added.
+    pub fn presign(
+        &mut self,
+        pubkey: VerifyingKey,
+        count: u16,
+    ) -> (QueryId, BoxFuture<'_, Result<usize, SwarmError>>) {
+        let query_id = new_query_id();
+        let (tx, rx) = oneshot::channel::<Result<usize, SwarmError>>();
+        let send_message =
+            SwarmInput::Presign(query_id.clone(), pubkey.serialize().to_vec(), count, tx);
+        let sent = self.send_input(send_message);
         (
             query_id,
             Box::pin(async move {
-                let response = rx.await.map_err(|_| SwarmError::MessageProcessingError)?;
-                Ok(response)
+                sent?;
+                rx.await.map_err(|_| SwarmError::MessageProcessingError)?
             }),
         )
     }
 
-    pub fn sign(
+    pub fn reshare(
         &mut self,
         pubkey: VerifyingKey,
-        message: Vec<u8>,
-    ) -> (QueryId, BoxFuture<'_, Result<Signature, SwarmError>>) {
-        let (tx, rx) = oneshot::channel::<Signature>();
-        let query_id = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect::<String>();
-        let send_message =
-            SwarmInput::Sign(query_id.clone(), tx, pubkey.serialize().to_vec(), message);
-        let _ = self.input_tx.as_mut().unwrap().start_send(send_message);
+        new_config: SignerConfig,
+    ) -> (QueryId, BoxFuture<'_, Result<PublicKeyPackage, SwarmError>>) {
+        self.start_reshare(pubkey, new_config, Vec::new())
+    }
+
+    pub fn change_threshold(
+        &mut self,
+        pubkey: VerifyingKey,
+        new_min: u16,
+        new_total: u16,
+        new_peers: Vec<Multiaddr>,
+    ) -> (QueryId, BoxFuture<'_, Result<PublicKeyPackage, SwarmError>>) {
+        self.start_reshare(pubkey, SignerConfig::new(new_min, new_total), new_peers)
+    }
+
+    fn start_reshare(
+        &mut self,
+        pubkey: VerifyingKey,
+        new_config: SignerConfig,
+        new_peers: Vec<Multiaddr>,
+    ) -> (QueryId, BoxFuture<'_, Result<PublicKeyPackage, SwarmError>>) {
+        let (tx, rx) = oneshot::channel::<Result<PublicKeyPackage, SwarmError>>();
+        let query_id = new_query_id();
+        let send_message = SwarmInput::Reshare(
+            query_id.clone(),
+            pubkey.serialize().to_vec(),
+            new_config,
+            new_peers,
+            tx,
+        );
+        let sent = self.send_input(send_message);
         (
             query_id,
             Box::pin(async move {
-                let response = rx.await.map_err(|_| SwarmError::MessageProcessingError)?;
-                Ok(response)
+                sent?;
+                rx.await.map_err(|_| SwarmError::MessageProcessingError)?
             }),
         )
     }
+
+    /// Cancelling an id that is unknown or has already finished does nothing.
+    pub fn cancel(&mut self, query_id: QueryId) -> Result<(), SwarmError> {
+        let send_message = SwarmInput::Cancel(query_id);
+        self.send_input(send_message)
+    }
+
+    /// While paused, new generation and signing invitations are declined so the requester can
+    /// pick another participant. Sessions already in flight run to completion.
+    pub fn set_accepting(&mut self, accepting: bool) -> Result<(), SwarmError> {
+        let send_message = SwarmInput::SetAccepting(accepting);
+        self.send_input(send_message)
+    }
+
+    pub fn shutdown(mut self) -> BoxFuture<'static, ()> {
+        let (tx, rx) = oneshot::channel::<()>();
+        let send_message = SwarmInput::Shutdown(tx);
+        let input_tx = self.input_tx.take();
+        Box::pin(async move {
+            // WAIT FOR ROOM RATHER THAN DROPPING THE SHUTDOWN REQUEST
+            if let Some(mut input_tx) = input_tx {
+                let _ = input_tx.send(send_message).await;
+            }
+            let _ = rx.await;
+        })
+    }
+
+    pub fn listeners(&mut self) -> BoxFuture<'_, Result<Vec<Multiaddr>, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Vec<Multiaddr>>();
+        let send_message = SwarmInput::Listeners(tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)
+        })
+    }
+
+    pub fn active_sessions(&mut self) -> BoxFuture<'_, Result<usize, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<usize>();
+        let send_message = SwarmInput::ActiveSessions(tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)
+        })
+    }
+
+    pub fn key_package(
+        &mut self,
+        pubkey: VerifyingKey,
+    ) -> BoxFuture<'_, Result<KeyPackage, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Option<KeyPackage>>();
+        let send_message = SwarmInput::KeyPackage(pubkey.serialize().to_vec(), tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            let response = rx.await.map_err(|_| SwarmError::MessageProcessingError)?;
+            response.ok_or(SwarmError::DatabaseError)
+        })
+    }
+
+    pub fn public_key_package(
+        &mut self,
+        pubkey: VerifyingKey,
+    ) -> BoxFuture<'_, Result<PublicKeyPackage, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Option<PublicKeyPackage>>();
+        let send_message = SwarmInput::PublicKeyPackage(pubkey.serialize().to_vec(), tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            let response = rx.await.map_err(|_| SwarmError::MessageProcessingError)?;
+            response.ok_or(SwarmError::DatabaseError)
+        })
+    }
+
+    pub fn config_for(
+        &mut self,
+        pubkey: VerifyingKey,
+    ) -> BoxFuture<'_, Result<Option<SignerConfig>, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Option<SignerConfig>>();
+        let send_message = SwarmInput::SignerConfig(pubkey.serialize().to_vec(), tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)
+        })
+    }
+
+    pub fn list_keys(&mut self) -> BoxFuture<'_, Result<Vec<VerifyingKey>, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Vec<VerifyingKey>>();
+        let send_message = SwarmInput::ListKeys(tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)
+        })
+    }
+
+    /// Identifiers are derived from the peer id with `Identifier::derive`, so a peer keeps the
+    /// same identifier in every group it joins, whatever order it was selected in. A reshare keeps
+    /// the identifiers of existing participants.
+    pub fn participant_map(
+        &mut self,
+        pubkey: VerifyingKey,
+    ) -> BoxFuture<'_, Result<Vec<(PeerId, Identifier)>, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Vec<(PeerId, Identifier)>>();
+        let send_message = SwarmInput::ParticipantMap(pubkey.serialize().to_vec(), tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)
+        })
+    }
+
+    pub fn export_group(
+        &mut self,
+        pubkey: VerifyingKey,
+    ) -> BoxFuture<'_, Result<GroupBundle, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Option<GroupBundle>>();
+        let send_message = SwarmInput::ExportGroup(pubkey.serialize().to_vec(), tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            let response = rx.await.map_err(|_| SwarmError::MessageProcessingError)?;
+            response.ok_or(SwarmError::UnknownKey)
+        })
+    }
+
+    pub fn import_group(&mut self, bundle: GroupBundle) -> BoxFuture<'_, Result<(), SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Result<(), SwarmError>>();
+        let send_message = SwarmInput::ImportGroup(Box::new(bundle), tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            rx.await.map_err(|_| SwarmError::MessageProcessingError)?
+        })
+    }
+
+    pub fn group_status(
+        &mut self,
+        pubkey: VerifyingKey,
+    ) -> BoxFuture<'_, Result<GroupStatus, SwarmError>> {
+        let (tx, rx) = oneshot::channel::<Option<GroupStatus>>();
+        let send_message = SwarmInput::GroupStatus(pubkey.serialize().to_vec(), tx);
+        let sent = self.send_input(send_message);
+        Box::pin(async move {
+            sent?;
+            let response = rx.await.map_err(|_| SwarmError::MessageProcessingError)?;
+            response.ok_or(SwarmError::DatabaseError)
+        })
+    }
+
+    /// Resolves to a snapshot with `alive` unset if the background task has stopped.
+    pub fn health(&mut self) -> BoxFuture<'_, HealthSnapshot> {
+        let (tx, rx) = oneshot::channel::<HealthSnapshot>();
+        let _ = self.send_input(SwarmInput::Health(tx));
+        Box::pin(async move { rx.await.unwrap_or_default() })
+    }
 }
 
-fn create_libp2p_swarm(config: &Swarm) -> Result<Libp2pSwarm<Behaviour>, SwarmError> {
+#[cfg(feature = "blocking")]
+impl Swarm {
+    /// Blocks the calling thread until the generation resolves. The swarm loop must run on an
+    /// executor driven by another thread, and this must not be called from within an async
+    /// context, where it would stall the runtime.
+    pub fn generate_blocking(
+        &mut self,
+        min_threshold: u16,
+        total_peers: u16,
+    ) -> Result<VerifyingKey, SwarmError> {
+        futures::executor::block_on(self.generate(min_threshold, total_peers).1)
+    }
+
+    /// Blocking counterpart of [`Swarm::sign`], with the same restrictions as
+    /// [`Swarm::generate_blocking`].
+    pub fn sign_blocking(
+        &mut self,
+        pubkey: VerifyingKey,
+        message: Vec<u8>,
+    ) -> Result<Signature, SwarmError> {
+        futures::executor::block_on(self.sign(pubkey, message).1)
+    }
+}
+
+fn is_valid_query_id(query_id: &QueryId) -> bool {
+    !query_id.is_empty() && !query_id.starts_with(APP_TOPIC_PREFIX)
+}
+
+// CALLING next BEFORE exec IS A CONFIGURATION ERROR, NOT A PANIC OR AN ENDLESS STREAM
+fn unstarted_output() -> mpsc::UnboundedReceiver<SwarmOutput> {
+    let (mut output_tx, output_rx) = mpsc::unbounded();
+    let _ = output_tx.start_send(SwarmOutput::Error(SwarmError::ConfigurationError));
+    output_rx
+}
+
+pub(crate) fn new_query_id() -> QueryId {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect::<String>()
+}
+
+type ListenFailures = Vec<(Multiaddr, TransportError<std::io::Error>)>;
+
+type CreatedSwarm = (
+    Libp2pSwarm<Behaviour>,
+    Vec<ListenerId>,
+    ListenFailures,
+    Option<kad::QueryId>,
+);
+
+fn create_libp2p_swarm(config: &Swarm) -> Result<CreatedSwarm, SwarmError> {
+    let connection_limits = ConnectionLimits::default()
+        .with_max_established(Some(config.connection_limits.max_established))
+        .with_max_pending_incoming(Some(config.connection_limits.max_pending))
+        .with_max_pending_outgoing(Some(config.connection_limits.max_pending));
+    let local_peer_id = config.key.public().to_peer_id();
+    let (relay_transport, relay_client) = if config.relay {
+        let (relay_transport, relay_client) = relay::client::new(local_peer_id);
+        (Some(relay_transport), Some(relay_client))
+    } else {
+        (None, None)
+    };
+    let allowed_peers = config.allowed_peers.as_ref().map(|peers| {
+        let mut behaviour = allow_block_list::Behaviour::<AllowedPeers>::default();
+        for peer in peers {
+            behaviour.allow_peer(*peer);
+        }
+        behaviour
+    });
+    let mut blocked_peers = allow_block_list::Behaviour::<BlockedPeers>::default();
+    for peer in &config.denied_peers {
+        blocked_peers.block_peer(*peer);
+    }
+    // PEERS ON ANOTHER NETWORK SHARE NO REQUEST-RESPONSE PROTOCOL AND FAIL IDENTIFY
+    let protocol_version = network_protocol(config.network_id.as_deref());
+    let req_res_protocol = StreamProtocol::try_from_owned(protocol_version.clone())
+        .map_err(|_| SwarmError::ConfigurationError)?;
     let behavior = Behaviour {
-        gossipsub: gossipsub::Behaviour::new(
-            gossipsub::MessageAuthenticity::Signed(config.key.clone()),
-            gossipsub::ConfigBuilder::default().build().unwrap(),
-        )
-        .map_err(|_| SwarmError::ConfigurationError)?,
+        allowed_peers: Toggle::from(allowed_peers),
+        autonat: autonat::Behaviour::new(local_peer_id, autonat::Config::default()),
+        blocked_peers,
+        connection_limits: connection_limits::Behaviour::new(connection_limits),
+        dcutr: Toggle::from(config.relay.then(|| dcutr::Behaviour::new(local_peer_id))),
+        gossipsub: create_gossipsub(config)?,
         identify: identify::Behaviour::new(identify::Config::new(
-            PROTOCOL_VERSION.clone(),
+            protocol_version,
             config.key.public(),
         )),
         kad: Kademlia::with_config(
             config.key.public().to_peer_id(),
             MemoryStore::new(config.key.public().to_peer_id()),
-            KademliaConfig::default(),
+            create_kademlia_config(config)?,
         ),
-        req_res: request_response::cbor::Behaviour::new(
-            [(
-                StreamProtocol::new(&PROTOCOL_VERSION),
-                ProtocolSupport::Full,
-            )],
-            request_response::Config::default(),
+        relay_client: Toggle::from(relay_client),
+        req_res: direct::Behaviour::new(
+            request_response::cbor::Behaviour::new(
+                [(req_res_protocol, ProtocolSupport::Full)],
+                request_response::Config::default()
+                    .with_request_timeout(config.request_response.request_timeout)
+                    .with_max_concurrent_streams(config.request_response.max_concurrent_streams),
+            ),
+            local_peer_id,
         ),
     };
-    #[cfg(feature = "tokio")]
-    let transport = tcp::tokio::Transport::default();
-    #[cfg(not(feature = "tokio"))]
-    let transport = tcp::async_io::Transport::default();
-
-    let transport = transport
-        .upgrade(Version::V1Lazy)
-        .authenticate(
-            noise::Config::new(&config.key.clone()).map_err(|_| SwarmError::ConfigurationError)?,
-        )
-        .multiplex(yamux::Config::default())
-        .boxed();
+    let transport = create_transport(config, relay_transport)?;
+    #[cfg(feature = "metrics")]
+    let transport = config.metrics.bandwidth.wrap(transport);
 
     let swarm_config = Libp2pConfig::with_executor(config.executor)
-        .with_idle_connection_timeout(Duration::from_secs(60));
+        .with_idle_connection_timeout(config.idle_connection_timeout);
     let mut swarm = Libp2pSwarm::new(
         transport,
         behavior,
         config.key.public().to_peer_id(),
         swarm_config,
     );
+    // STAY A SERVER UNTIL AUTONAT REPORTS THE NODE AS UNREACHABLE
     swarm.behaviour_mut().kad.set_mode(Some(Mode::Server));
-    config.addresses.iter().for_each(|address| {
-        let _ = swarm.listen_on(address.clone());
-    });
-    Ok(swarm)
+    if let Some(peer_cache) = &config.peer_cache {
+        for (peer, addresses) in load_peer_cache(peer_cache)? {
+            for address in addresses {
+                swarm.behaviour_mut().kad.add_address(&peer, address);
+            }
+        }
+    }
+    for address in &config.bootstrap_peers {
+        let peer = peerid_from_multiaddress(address).ok_or(SwarmError::InvalidMultiaddr)?;
+        swarm
+            .behaviour_mut()
+            .kad
+            .add_address(&peer, address.clone());
+        let _ = swarm.dial(address.clone());
+    }
+    let bootstrap_query = if config.peer_cache.is_some() || !config.bootstrap_peers.is_empty() {
+        swarm.behaviour_mut().kad.bootstrap().ok()
+    } else {
+        None
+    };
+    let mut listeners = Vec::new();
+    let mut listen_failures = Vec::new();
+    for address in &config.addresses {
+        match swarm.listen_on(address.clone()) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => listen_failures.push((address.clone(), e)),
+        }
+    }
+    // ONLY FAIL OUTRIGHT WHEN NO ADDRESS COULD BE BOUND
+    if listeners.is_empty() && !listen_failures.is_empty() {
+        let (address, e) = listen_failures.remove(0);
+        return Err(SwarmError::ListenFailed(address, e));
+    }
+    Ok((swarm, listeners, listen_failures, bootstrap_query))
+}
+
+fn create_kademlia_config(config: &Swarm) -> Result<KademliaConfig, SwarmError> {
+    let mut kademlia_config = KademliaConfig::default();
+    let protocol = StreamProtocol::try_from_owned(format!(
+        "{}/kad",
+        network_protocol(config.network_id.as_deref())
+    ))
+    .map_err(|_| SwarmError::ConfigurationError)?;
+    kademlia_config.set_protocol_names(vec![protocol]);
+    Ok(kademlia_config)
+}
+
+fn create_gossipsub(config: &Swarm) -> Result<Gossipsub, SwarmError> {
+    let mut gossipsub_config = match &config.gossipsub_config {
+        Some(gossipsub_config) => gossipsub::ConfigBuilder::from(gossipsub_config.clone()),
+        None => gossipsub::ConfigBuilder::default(),
+    };
+    gossipsub_config.max_transmit_size(MAX_MESSAGE_SIZE);
+    // HOLD MESSAGES BACK FROM THE MESH UNTIL THE VALIDATOR HAS SEEN THEM
+    if config.message_validator.is_some() {
+        gossipsub_config.validate_messages();
+    }
+    let authenticity = match config.authenticity {
+        Authenticity::Signed => gossipsub::MessageAuthenticity::Signed(config.key.clone()),
+        Authenticity::Author => {
+            gossipsub_config.validation_mode(gossipsub::ValidationMode::Permissive);
+            gossipsub::MessageAuthenticity::Author(config.key.public().to_peer_id())
+        }
+        Authenticity::Anonymous => {
+            gossipsub_config
+                .validation_mode(gossipsub::ValidationMode::Anonymous)
+                .message_id_fn(|message| {
+                    let mut hasher = DefaultHasher::new();
+                    message.topic.hash(&mut hasher);
+                    message.data.hash(&mut hasher);
+                    gossipsub::MessageId::from(hasher.finish().to_string())
+                });
+            gossipsub::MessageAuthenticity::Anonymous
+        }
+    };
+    let gossipsub_config = gossipsub_config
+        .build()
+        .map_err(|e| SwarmError::GossipsubConfig(e.into()))?;
+    #[cfg(feature = "metrics")]
+    let gossipsub = gossipsub::Behaviour::new_with_transform(
+        authenticity,
+        gossipsub_config,
+        None,
+        CountingTransform {
+            metrics: config.metrics.clone(),
+        },
+    );
+    #[cfg(not(feature = "metrics"))]
+    let gossipsub = gossipsub::Behaviour::new(authenticity, gossipsub_config);
+    let mut gossipsub = gossipsub.map_err(|e| SwarmError::GossipsubConfig(e.into()))?;
+    if let Some(peer_scoring) = &config.peer_scoring {
+        gossipsub
+            .with_peer_score(peer_scoring.params.clone(), peer_scoring.thresholds.clone())
+            .map_err(|e| SwarmError::GossipsubConfig(e.into()))?;
+    }
+    Ok(gossipsub)
+}
+
+fn create_transport(
+    config: &Swarm,
+    relay_transport: Option<relay::client::Transport>,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, SwarmError> {
+    #[cfg(feature = "tokio")]
+    let tcp_transport = tcp::tokio::Transport::default();
+    #[cfg(not(feature = "tokio"))]
+    let tcp_transport = tcp::async_io::Transport::default();
+
+    #[cfg(feature = "websocket")]
+    let tcp_transport = {
+        // RESOLVE /dns ADDRESSES, WHICH WEBSOCKET PEERS ARE USUALLY REACHED THROUGH
+        #[cfg(feature = "tokio")]
+        let ws_transport = dns::tokio::Transport::system(tcp::tokio::Transport::default())
+            .map_err(SwarmError::DnsSetup)?;
+        #[cfg(not(feature = "tokio"))]
+        let ws_transport = dns::async_std::Transport::system2(tcp::async_io::Transport::default())
+            .map_err(SwarmError::DnsSetup)?;
+        OrTransport::new(websocket::WsConfig::new(ws_transport), tcp_transport)
+    };
+
+    let tcp_transport = secure_transport(tcp_transport, config)?;
+
+    #[cfg(feature = "tokio")]
+    let quic_transport = quic::tokio::Transport::new(quic::Config::new(&config.key));
+    #[cfg(not(feature = "tokio"))]
+    let quic_transport = quic::async_std::Transport::new(quic::Config::new(&config.key));
+
+    let quic_transport = quic_transport
+        .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)))
+        .boxed();
+
+    let transport = match config.transport {
+        #[cfg(feature = "test-util")]
+        TransportKind::Memory => secure_transport(MemoryTransport::default(), config)?,
+        TransportKind::Tcp => tcp_transport,
+        TransportKind::Quic => quic_transport,
+        TransportKind::Both => OrTransport::new(quic_transport, tcp_transport)
+            .map(|output, _| output.into_inner())
+            .boxed(),
+    };
+    let Some(relay_transport) = relay_transport else {
+        return Ok(transport);
+    };
+    let relay_transport = secure_transport(relay_transport, config)?;
+    Ok(OrTransport::new(relay_transport, transport)
+        .map(|output, _| output.into_inner())
+        .boxed())
+}
+
+// QUIC ALWAYS USES TLS 1.3, SO THIS ONLY APPLIES TO STREAM TRANSPORTS
+fn secure_transport<T>(
+    transport: T,
+    config: &Swarm,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, SwarmError>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    let transport = transport.upgrade(Version::V1Lazy);
+    let transport = match config.security {
+        Security::Noise => transport
+            .authenticate(noise::Config::new(&config.key).map_err(SwarmError::NoiseSetup)?)
+            .multiplex(yamux::Config::default())
+            .boxed(),
+        #[cfg(feature = "tls")]
+        Security::Tls => transport
+            .authenticate(tls::Config::new(&config.key).map_err(SwarmError::TlsSetup)?)
+            .multiplex(yamux::Config::default())
+            .boxed(),
+    };
+    Ok(transport)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::PROTOCOL_VERSION;
+
+    fn kademlia_protocol(builder: Builder) -> String {
+        let config = builder.build();
+        let peer = config.key.public().to_peer_id();
+        let kad = Kademlia::with_config(
+            peer,
+            MemoryStore::new(peer),
+            create_kademlia_config(&config).unwrap(),
+        );
+        kad.protocol_names()[0].to_string()
+    }
+
+    #[test]
+    fn kademlia_protocol_follows_revision_and_network() {
+        let default = kademlia_protocol(Swarm::builder());
+        assert_eq!(default, format!("{}/kad", *PROTOCOL_VERSION));
+        let scoped = kademlia_protocol(Swarm::builder().set_network_id("testnet".to_string()));
+        assert_eq!(scoped, format!("{}/testnet/kad", *PROTOCOL_VERSION));
+    }
 }
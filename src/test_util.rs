@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use crate::{
+    swarm::{Swarm, SwarmError, TransportKind},
+    Multiaddr, MultiaddrProtocol, Signature, VerifyingKey,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `n` swarms connected to each other over the in-memory transport. The helpers run on the
+/// first swarm, which takes part in every generation it starts.
+pub struct TestCluster {
+    pub swarms: Vec<Swarm>,
+}
+
+impl TestCluster {
+    pub async fn new(n: usize) -> Result<Self, SwarmError> {
+        let mut swarms = Vec::with_capacity(n);
+        let mut addresses = Vec::with_capacity(n);
+        for _ in 0..n {
+            let address: Multiaddr = MultiaddrProtocol::Memory(rand::random()).into();
+            let mut swarm = Swarm::builder()
+                .set_transport(TransportKind::Memory)
+                .set_addresses(vec![address.clone()])
+                .build();
+            swarm.exec()?;
+            addresses.push(address.with(MultiaddrProtocol::P2p(swarm.key.public().to_peer_id())));
+            swarms.push(swarm);
+        }
+        for (index, swarm) in swarms.iter_mut().enumerate() {
+            for (other, address) in addresses.iter().enumerate() {
+                if other != index {
+                    swarm.add_peer(address.clone())?;
+                }
+                if other < index {
+                    swarm.dial(address.clone()).await?;
+                }
+            }
+        }
+        for swarm in &mut swarms {
+            swarm
+                .wait_for_peers(n.saturating_sub(1), CONNECT_TIMEOUT)
+                .await?;
+        }
+        Ok(Self { swarms })
+    }
+
+    pub async fn generate(
+        &mut self,
+        min_threshold: u16,
+        total_peers: u16,
+    ) -> Result<VerifyingKey, SwarmError> {
+        let swarm = self
+            .swarms
+            .first_mut()
+            .ok_or(SwarmError::ConfigurationError)?;
+        swarm.generate(min_threshold, total_peers).1.await
+    }
+
+    pub async fn sign(
+        &mut self,
+        pubkey: VerifyingKey,
+        message: Vec<u8>,
+    ) -> Result<Signature, SwarmError> {
+        let swarm = self
+            .swarms
+            .first_mut()
+            .ok_or(SwarmError::ConfigurationError)?;
+        swarm.sign(pubkey, message).1.await
+    }
+}
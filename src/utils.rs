@@ -1,10 +1,186 @@
-use libp2p::{PeerId, Swarm as Libp2pSwarm};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use frost_ed25519::{round1::SigningCommitments, round2::SignatureShare, SigningPackage};
+use libp2p::{gossipsub::TopicHash, PeerId, Swarm as Libp2pSwarm};
 use once_cell::sync::Lazy;
 
-use crate::{Behaviour, Multiaddr, MultiaddrProtocol};
+use crate::swarm::SwarmError;
+use crate::{
+    database::write_private, Behaviour, Identifier, Keypair, Multiaddr, MultiaddrProtocol,
+    PublicKeyPackage, Signature, VerifyingKey,
+};
+
+pub(crate) const CONTEXT_TAG: &[u8] = b"FROSTore/context/v1";
+
+/// Leading byte of every encoding produced by the `serialize_*` helpers.
+pub const ENCODING_VERSION: u8 = 1;
+
+/// Bumped on every incompatible change to the wire format or to how identifiers are derived,
+/// so nodes built from the same crate version still refuse each other across such a change.
+pub const PROTOCOL_REVISION: u32 = 2;
+
+pub static PROTOCOL_VERSION: Lazy<String> = Lazy::new(|| {
+    format!(
+        "/FROSTore/{}/{PROTOCOL_REVISION}",
+        env!("CARGO_PKG_VERSION")
+    )
+});
+
+/// `PROTOCOL_VERSION`, suffixed with `/{network_id}` when one is configured.
+pub(crate) fn network_protocol(network_id: Option<&str>) -> String {
+    match network_id {
+        Some(network_id) => format!("{}/{network_id}", *PROTOCOL_VERSION),
+        None => PROTOCOL_VERSION.clone(),
+    }
+}
+
+pub fn verify(key: &VerifyingKey, msg: &[u8], sig: &Signature) -> Result<(), SwarmError> {
+    key.verify(msg, sig)
+        .map_err(|_| SwarmError::InvalidSignature)
+}
+
+/// The group key a package verifies against, usable without a running swarm.
+pub fn group_public_key(pkg: &PublicKeyPackage) -> VerifyingKey {
+    *pkg.verifying_key()
+}
+
+/// Checks the share `id` produced for `msg` against its verifying share in `pkg`, given the
+/// round-one `commitments` of every signer in the session. This is the per-share check FROST
+/// runs after a failed aggregation, so a coordinator can name the bad signer itself.
+pub fn verify_share(
+    pkg: &PublicKeyPackage,
+    id: Identifier,
+    commitments: &BTreeMap<Identifier, SigningCommitments>,
+    partial: &SignatureShare,
+    msg: &[u8],
+) -> Result<(), SwarmError> {
+    let verifying_share = pkg
+        .verifying_shares()
+        .get(&id)
+        .ok_or(SwarmError::ConfigurationError)?;
+    let commitment = commitments.get(&id).ok_or(SwarmError::ConfigurationError)?;
+    let signing_package = SigningPackage::new(commitments.clone(), msg);
+    let binding_factors =
+        frost_core::compute_binding_factor_list(&signing_package, pkg.verifying_key(), &[]);
+    let binding_factor = binding_factors
+        .get(&id)
+        .ok_or(SwarmError::ConfigurationError)?;
+    let group_commitment = frost_core::compute_group_commitment(&signing_package, &binding_factors)
+        .map_err(|_| SwarmError::InvalidEncoding)?;
+    let challenge = frost_core::challenge(&group_commitment.to_element(), pkg.verifying_key(), msg);
+    let lambda_i = frost_core::derive_interpolating_value(&id, &signing_package)
+        .map_err(|_| SwarmError::ConfigurationError)?;
+    partial
+        .verify(
+            id,
+            &commitment.to_group_commitment_share(binding_factor),
+            verifying_share,
+            lambda_i,
+            &challenge,
+        )
+        .map_err(|_| SwarmError::InvalidSignatureShare(id))
+}
+
+pub fn verify_with_context(
+    key: &VerifyingKey,
+    context: &[u8],
+    msg: &[u8],
+    sig: &Signature,
+) -> Result<(), SwarmError> {
+    verify(key, &contextualize(context, msg)?, sig)
+}
+
+/// Participants only sign a message carrying the context tag when the requester asked for a
+/// context signature, so a plain request can never produce one.
+pub(crate) fn check_context(msg: &[u8], contextual: bool) -> Result<(), SwarmError> {
+    if msg.starts_with(CONTEXT_TAG) != contextual {
+        return Err(SwarmError::ReservedMessage);
+    }
+    Ok(())
+}
+
+/// FROST-Ed25519 has no context input to its challenge, so the context is bound by signing
+/// `CONTEXT_TAG || len(context) || context || message` instead.
+pub(crate) fn contextualize(context: &[u8], msg: &[u8]) -> Result<Vec<u8>, SwarmError> {
+    let len = u8::try_from(context.len()).map_err(|_| SwarmError::InvalidContext)?;
+    let mut bytes = CONTEXT_TAG.to_vec();
+    bytes.push(len);
+    bytes.extend_from_slice(context);
+    bytes.extend_from_slice(msg);
+    Ok(bytes)
+}
+
+/// Version 1 layout: `[ENCODING_VERSION, R (32 bytes), z (32 bytes)]`, the Ed25519 signature
+/// encoding behind a version byte.
+pub fn serialize_signature(signature: &Signature) -> Vec<u8> {
+    let mut bytes = vec![ENCODING_VERSION];
+    bytes.extend(signature.serialize());
+    bytes
+}
+
+pub fn deserialize_signature(bytes: &[u8]) -> Result<Signature, SwarmError> {
+    match bytes.split_first() {
+        Some((&ENCODING_VERSION, signature)) => {
+            let signature = signature
+                .try_into()
+                .map_err(|_| SwarmError::InvalidEncoding)?;
+            Signature::deserialize(signature).map_err(|_| SwarmError::InvalidEncoding)
+        }
+        _ => Err(SwarmError::InvalidEncoding),
+    }
+}
+
+/// Version 1 layout: `[ENCODING_VERSION, A (32 bytes)]`, the compressed Ed25519 point behind a
+/// version byte.
+pub fn serialize_verifying_key(key: &VerifyingKey) -> Vec<u8> {
+    let mut bytes = vec![ENCODING_VERSION];
+    bytes.extend(key.serialize());
+    bytes
+}
+
+pub fn deserialize_verifying_key(bytes: &[u8]) -> Result<VerifyingKey, SwarmError> {
+    match bytes.split_first() {
+        Some((&ENCODING_VERSION, key)) => {
+            let key = key.try_into().map_err(|_| SwarmError::InvalidEncoding)?;
+            VerifyingKey::deserialize(key).map_err(|_| SwarmError::InvalidEncoding)
+        }
+        _ => Err(SwarmError::InvalidEncoding),
+    }
+}
 
-pub static PROTOCOL_VERSION: Lazy<String> =
-    Lazy::new(|| format!("/FROSTore/{}", env!("CARGO_PKG_VERSION")));
+/// Reuse the same keypair across restarts to keep a stable peer id; shares are mapped to it.
+pub fn save_keypair(key: &Keypair, path: &Path) -> Result<(), SwarmError> {
+    let bytes = key
+        .to_protobuf_encoding()
+        .map_err(|_| SwarmError::StorageError)?;
+    write_private(path, &bytes)
+}
+
+pub fn load_keypair(path: &Path) -> Result<Keypair, SwarmError> {
+    let bytes = fs::read(path).map_err(|_| SwarmError::StorageError)?;
+    Keypair::from_protobuf_encoding(&bytes).map_err(|_| SwarmError::StorageError)
+}
+
+/// Deterministic identities are meant for test networks; production nodes should generate keys.
+pub fn keypair_from_seed(seed: [u8; 32]) -> Keypair {
+    Keypair::ed25519_from_bytes(seed).expect("a 32 byte seed is always a valid ed25519 key")
+}
+
+pub(crate) fn identifier_for(peer: &PeerId) -> Result<Identifier, SwarmError> {
+    let mut bytes = peer.to_bytes();
+    if let Ok(identifier) = Identifier::derive(&bytes) {
+        return Ok(identifier);
+    }
+    // A PEER ID THAT HASHES TO ZERO IS RETRIED WITH A COUNTER SUFFIX
+    bytes.push(0);
+    for counter in 1..=u8::MAX {
+        *bytes.last_mut().ok_or(SwarmError::ConfigurationError)? = counter;
+        if let Ok(identifier) = Identifier::derive(&bytes) {
+            return Ok(identifier);
+        }
+    }
+    Err(SwarmError::ConfigurationError)
+}
 
 pub(crate) fn peerid_from_multiaddress(multiaddr: &Multiaddr) -> Option<PeerId> {
     multiaddr.iter().find_map(|protocol| {
@@ -17,6 +193,8 @@ pub(crate) fn peerid_from_multiaddress(multiaddr: &Multiaddr) -> Option<PeerId>
 }
 
 pub(crate) fn get_peers_list(swarm: &mut Libp2pSwarm<Behaviour>) -> Vec<PeerId> {
+    let local_peer_id = *swarm.local_peer_id();
+    // NEVER SELECT THE INITIATOR AS ONE OF ITS OWN REMOTE PARTICIPANTS
     swarm
         .behaviour_mut()
         .kad
@@ -27,5 +205,48 @@ pub(crate) fn get_peers_list(swarm: &mut Libp2pSwarm<Behaviour>) -> Vec<PeerId>
                 .map(|entry| *entry.node.key.preimage())
                 .collect::<Vec<_>>()
         })
+        .filter(|peer| *peer != local_peer_id)
         .collect::<Vec<_>>()
 }
+
+/// Publishes a protocol message and hands a copy back to the local node, which gossipsub never
+/// delivers its own messages to.
+pub(crate) fn publish(swarm: &mut Libp2pSwarm<Behaviour>, topic: TopicHash, data: Vec<u8>) {
+    let _ = swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), data.clone());
+    swarm.behaviour_mut().req_res.loop_back(topic, data);
+}
+
+pub(crate) fn load_peer_cache(path: &Path) -> Result<Vec<(PeerId, Vec<Multiaddr>)>, SwarmError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let bytes = fs::read(path).map_err(|_| SwarmError::StorageError)?;
+    bincode::deserialize(&bytes).map_err(|_| SwarmError::StorageError)
+}
+
+pub(crate) fn save_peer_cache(
+    swarm: &mut Libp2pSwarm<Behaviour>,
+    path: &Path,
+) -> Result<(), SwarmError> {
+    let peers = swarm
+        .behaviour_mut()
+        .kad
+        .kbuckets()
+        .flat_map(|bucket| {
+            bucket
+                .iter()
+                .map(|entry| {
+                    (
+                        *entry.node.key.preimage(),
+                        entry.node.value.iter().cloned().collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let bytes = bincode::serialize(&peers).map_err(|_| SwarmError::StorageError)?;
+    write_private(path, &bytes)
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use frostore::{
+    swarm::{SwarmError, SwarmOutput},
+    utils::verify,
+    SignerConfig, TestCluster,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generate_and_sign() {
+    let mut cluster = TestCluster::new(4).await.unwrap();
+    let pubkey = cluster.generate(2, 3).await.unwrap();
+    let message = b"cluster test".to_vec();
+    let signature = cluster.sign(pubkey, message.clone()).await.unwrap();
+    verify(&pubkey, &message, &signature).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reshare_keeps_group_key() {
+    let mut cluster = TestCluster::new(4).await.unwrap();
+    let pubkey = cluster.generate(2, 3).await.unwrap();
+    // GENERATION ONLY RESOLVES ONCE EVERY PARTICIPANT HAS STORED ITS SHARE
+    let mut old_shares = Vec::new();
+    for (index, swarm) in cluster.swarms.iter_mut().enumerate() {
+        if let Ok(key_package) = swarm.key_package(pubkey).await {
+            old_shares.push((index, *key_package.signing_share()));
+        }
+    }
+    assert_eq!(old_shares.len(), 3);
+    let package = cluster.swarms[0]
+        .reshare(pubkey, SignerConfig::new(2, 3))
+        .1
+        .await
+        .unwrap();
+    assert_eq!(*package.verifying_key(), pubkey);
+    // PARTICIPANTS STORE THEIR NEW SHARE ONCE THE REQUESTER'S FINISH MESSAGE ARRIVES
+    for (index, old_share) in old_shares {
+        let mut attempts = 0;
+        while cluster.swarms[index]
+            .key_package(pubkey)
+            .await
+            .is_ok_and(|key_package| *key_package.signing_share() == old_share)
+        {
+            attempts += 1;
+            assert!(attempts < 100, "share of swarm {index} was never refreshed");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    let message = b"after reshare".to_vec();
+    let signature = cluster.sign(pubkey, message.clone()).await.unwrap();
+    verify(&pubkey, &message, &signature).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn initiator_fills_one_of_the_seats() {
+    let mut cluster = TestCluster::new(3).await.unwrap();
+    let pubkey = cluster.generate(2, 3).await.unwrap();
+    for swarm in cluster.swarms.iter_mut() {
+        assert!(swarm.key_package(pubkey).await.is_ok());
+    }
+    let message = b"every seat signs".to_vec();
+    let signature = cluster.sign(pubkey, message.clone()).await.unwrap();
+    verify(&pubkey, &message, &signature).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generate_without_enough_peers_is_rejected() {
+    let mut cluster = TestCluster::new(1).await.unwrap();
+    assert!(matches!(
+        cluster.generate(2, 2).await,
+        Err(SwarmError::InsufficientPeers)
+    ));
+    assert!(matches!(
+        cluster.swarms[0].can_generate(2).await,
+        Err(SwarmError::InsufficientPeers)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generation_releases_its_topics() {
+    let mut cluster = TestCluster::new(4).await.unwrap();
+    let mut baseline = Vec::new();
+    for swarm in cluster.swarms.iter_mut() {
+        baseline.push(swarm.health().await.subscribed_topics);
+    }
+    let pubkey = cluster.generate(2, 3).await.unwrap();
+    // ONLY THE GROUP TOPIC OUTLIVES THE SESSION, AND ONLY ON SHARE HOLDERS
+    let mut holders = Vec::new();
+    for (index, swarm) in cluster.swarms.iter_mut().enumerate() {
+        let mut attempts = 0;
+        loop {
+            let holds_share = swarm.key_package(pubkey).await.is_ok();
+            let expected = baseline[index] + usize::from(holds_share);
+            if swarm.health().await.subscribed_topics == expected {
+                if holds_share {
+                    holders.push(index);
+                }
+                break;
+            }
+            attempts += 1;
+            assert!(attempts < 100, "swarm {index} kept its session topics");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    assert_eq!(holders.len(), 3);
+    assert!(holders.contains(&0), "the initiator holds no share");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_thresholds_are_rejected_up_front() {
+    let mut cluster = TestCluster::new(4).await.unwrap();
+    for (min_threshold, total_peers) in [(1, 3), (5, 3)] {
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            cluster.generate(min_threshold, total_peers),
+        )
+        .await
+        .expect("an invalid threshold must fail without contacting peers");
+        assert!(matches!(result, Err(SwarmError::ConfigurationError)));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn two_keys_sign_independently() {
+    let mut cluster = TestCluster::new(4).await.unwrap();
+    let first = cluster.generate(2, 3).await.unwrap();
+    let second = cluster.generate(2, 3).await.unwrap();
+    assert_ne!(first, second);
+    let first_message = b"signed by the first key".to_vec();
+    let second_message = b"signed by the second key".to_vec();
+    let first_signature = cluster.sign(first, first_message.clone()).await.unwrap();
+    let second_signature = cluster.sign(second, second_message.clone()).await.unwrap();
+    verify(&first, &first_message, &first_signature).unwrap();
+    verify(&second, &second_message, &second_signature).unwrap();
+    assert!(verify(&second, &first_message, &first_signature).is_err());
+    assert!(verify(&first, &second_message, &second_signature).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reshare_of_an_unknown_key_is_reported() {
+    let mut cluster = TestCluster::new(4).await.unwrap();
+    let pubkey = cluster.generate(2, 3).await.unwrap();
+    // A SWARM OUTSIDE THE CLUSTER HAS NEVER SEEN THE KEY
+    let mut stranger = TestCluster::new(1).await.unwrap();
+    let result = stranger.swarms[0]
+        .reshare(pubkey, SignerConfig::new(2, 3))
+        .1
+        .await;
+    assert!(matches!(result, Err(SwarmError::UnknownKey)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancel_of_a_finished_request_is_silent() {
+    let mut cluster = TestCluster::new(1).await.unwrap();
+    let swarm = &mut cluster.swarms[0];
+    swarm.cancel("already-finished".to_string()).unwrap();
+    while let Ok(Some(output)) =
+        tokio::time::timeout(Duration::from_millis(500), swarm.next()).await
+    {
+        assert!(!matches!(output, SwarmOutput::Error(_)));
+    }
+}
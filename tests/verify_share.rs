@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use frost_ed25519::{
+    keys::{generate_with_dealer, IdentifierList, KeyPackage, PublicKeyPackage},
+    round1::{self, SigningCommitments},
+    round2::{self, SignatureShare},
+    Identifier, SigningPackage,
+};
+use frostore::{swarm::SwarmError, utils::verify_share};
+use rand::{rngs::StdRng, SeedableRng};
+
+const MSG: &[u8] = b"verify share";
+
+struct Session {
+    pkg: PublicKeyPackage,
+    commitments: BTreeMap<Identifier, SigningCommitments>,
+    signer: Identifier,
+    share: SignatureShare,
+    absent: Identifier,
+}
+
+// A 2-OF-3 GROUP WHERE THE FIRST TWO SIGNERS TAKE PART AND THE THIRD STAYS OUT
+fn session() -> Session {
+    let mut rng = StdRng::seed_from_u64(7);
+    let (shares, pkg) = generate_with_dealer(3, 2, IdentifierList::Default, &mut rng).unwrap();
+    let mut key_packages = shares
+        .into_iter()
+        .map(|(id, share)| (id, KeyPackage::try_from(share).unwrap()))
+        .collect::<BTreeMap<_, _>>();
+    let (absent, _) = key_packages.pop_last().unwrap();
+    let mut nonces = BTreeMap::new();
+    let mut commitments = BTreeMap::new();
+    for (id, key_package) in &key_packages {
+        let (nonce, commitment) = round1::commit(key_package.signing_share(), &mut rng);
+        nonces.insert(*id, nonce);
+        commitments.insert(*id, commitment);
+    }
+    let signing_package = SigningPackage::new(commitments.clone(), MSG);
+    let (signer, key_package) = key_packages.iter().next().unwrap();
+    let share = round2::sign(&signing_package, &nonces[signer], key_package).unwrap();
+    Session {
+        pkg,
+        commitments,
+        signer: *signer,
+        share,
+        absent,
+    }
+}
+
+#[test]
+fn verify_share_detects_a_flipped_bit() {
+    let session = session();
+    verify_share(
+        &session.pkg,
+        session.signer,
+        &session.commitments,
+        &session.share,
+        MSG,
+    )
+    .unwrap();
+
+    let mut bytes = session.share.serialize();
+    bytes[0] ^= 1;
+    let tampered = SignatureShare::deserialize(bytes).unwrap();
+    assert!(matches!(
+        verify_share(&session.pkg, session.signer, &session.commitments, &tampered, MSG),
+        Err(SwarmError::InvalidSignatureShare(culprit)) if culprit == session.signer
+    ));
+}
+
+#[test]
+fn verify_share_rejects_the_wrong_verifying_share() {
+    let session = session();
+    let mut verifying_shares = session.pkg.verifying_shares().clone();
+    let other = verifying_shares[&session.absent];
+    verifying_shares.insert(session.signer, other);
+    let pkg = PublicKeyPackage::new(verifying_shares, *session.pkg.verifying_key());
+    assert!(matches!(
+        verify_share(&pkg, session.signer, &session.commitments, &session.share, MSG),
+        Err(SwarmError::InvalidSignatureShare(culprit)) if culprit == session.signer
+    ));
+}
+
+#[test]
+fn verify_share_rejects_a_signer_without_commitments() {
+    let session = session();
+    assert!(matches!(
+        verify_share(
+            &session.pkg,
+            session.absent,
+            &session.commitments,
+            &session.share,
+            MSG,
+        ),
+        Err(SwarmError::ConfigurationError)
+    ));
+}